@@ -0,0 +1,411 @@
+//! What is seen in the distance by rays that escape the scene, which also lights it.
+
+use crate::{
+    image::{Color, Image},
+    math::{ConePdf, CosinePdf, MixturePdf, Pdf, Vector},
+    tonemap::LUMINANCE_WEIGHTS,
+    UsedRng,
+};
+use cgmath::{InnerSpace, VectorSpace};
+use derive_new::new;
+use easy_cast::{Conv, ConvFloat};
+use rand::Rng;
+use std::f64::consts::PI;
+
+/// The light arriving from infinitely far away in every direction.
+pub trait Background: Send + Sync {
+    /// The light arriving from the direction, which need not be a unit vector.
+    fn color(&self, direction: &Vector) -> Color;
+
+    /// Whether directions towards the background can be chosen by
+    /// [`random_direction`](Background::random_direction), so that it is sampled like the lights.
+    fn is_sampled(&self) -> bool {
+        false
+    }
+
+    /// The probability density with respect to solid angle of choosing the direction in
+    /// [`random_direction`](Background::random_direction).
+    fn pdf_value(&self, _direction: &Vector) -> f64 {
+        0.
+    }
+
+    /// A random unit direction, preferably towards the brighter parts of the background.
+    fn random_direction(&self, _rng: &mut UsedRng) -> Option<Vector> {
+        None
+    }
+}
+
+/// A single color in every direction.
+#[derive(new, Debug, Clone)]
+pub struct Solid {
+    color: Color,
+}
+impl Background for Solid {
+    fn color(&self, _direction: &Vector) -> Color {
+        self.color
+    }
+}
+
+/// A sky-like vertical gradient, blending from the `bottom` color straight down to the `top`
+/// color straight up.
+#[derive(new, Debug, Clone)]
+pub struct Gradient {
+    bottom: Color,
+    top: Color,
+}
+impl Gradient {
+    /// Bottom color of the default sky gradient.
+    pub const SKY_BOTTOM: Color = Color::new(1., 1., 1.);
+    /// Top color of the default sky gradient.
+    pub const SKY_TOP: Color = Color::new(0.5, 0.7, 1.);
+}
+impl Default for Gradient {
+    fn default() -> Self {
+        Self::new(Self::SKY_BOTTOM, Self::SKY_TOP)
+    }
+}
+impl Background for Gradient {
+    fn color(&self, direction: &Vector) -> Color {
+        let unit = direction.normalize();
+        self.bottom.lerp(self.top, 0.5 * (unit.y + 1.))
+    }
+}
+
+/// A panoramic image of the surroundings in the equirectangular projection, such as a high dynamic
+/// range photograph, which gives realistic lighting.
+///
+/// Straight up is the top of the image and the center looks along the negative z axis, with the
+/// positive x axis being a quarter of the way from the right edge.
+/// Directions are sampled in proportion to the brightness of the pixels, so that small bright
+/// features like the sun are found quickly.
+#[derive(Debug)]
+pub struct EnvironmentMap {
+    image: Image,
+    /// Angle in radians by which the image is turned about the vertical axis.
+    rotation: f64,
+    intensity: f64,
+    /// The running totals of the weight with which each pixel is sampled, in row major order.
+    cdf: Box<[f64]>,
+}
+impl EnvironmentMap {
+    /// The `image` surrounding the scene, unrotated and with its original brightness.
+    pub fn new(image: Image) -> Self {
+        let size = image.size();
+
+        // Pixels near the poles cover less of the sphere, and are sampled less to match
+        let mut total = 0.;
+        let cdf = image
+            .pixel_data()
+            .iter()
+            .enumerate()
+            .map(|(i, color)| {
+                let theta = PI * (f64::conv(i / size.width) + 0.5) / f64::conv(size.height);
+                total += color.dot(LUMINANCE_WEIGHTS).max(0.) * theta.sin();
+                total
+            })
+            .collect();
+
+        Self {
+            image,
+            rotation: 0.,
+            intensity: 1.,
+            cdf,
+        }
+    }
+
+    /// Turns the image by the angle in degrees about the vertical axis, counter-clockwise when
+    /// looking down.
+    pub fn with_rotation(mut self, degrees: f64) -> Self {
+        self.rotation = degrees.to_radians();
+        self
+    }
+
+    /// Scales the brightness of the image.
+    pub fn with_intensity(mut self, intensity: f64) -> Self {
+        self.intensity = intensity;
+        self
+    }
+
+    /// The sum of all of the pixel weights.
+    fn total_weight(&self) -> f64 {
+        self.cdf.last().copied().unwrap_or_default()
+    }
+
+    /// The index of the pixel seen in the unit direction.
+    fn pixel_index(&self, unit: &Vector) -> usize {
+        let size = self.image.size();
+        let phi = unit.x.atan2(-unit.z) - self.rotation;
+        let u = (0.5 + phi / (2. * PI)).rem_euclid(1.);
+        let v = unit.y.clamp(-1., 1.).acos() / PI;
+
+        let pixel = |x: f64, len: usize| usize::conv_floor(x * f64::conv(len)).min(len - 1);
+        pixel(v, size.height) * size.width + pixel(u, size.width)
+    }
+}
+impl Background for EnvironmentMap {
+    fn color(&self, direction: &Vector) -> Color {
+        self.image.pixel_data()[self.pixel_index(&direction.normalize())] * self.intensity
+    }
+
+    fn is_sampled(&self) -> bool {
+        self.total_weight() > 0.
+    }
+
+    /// Within each pixel directions are uniform in the image coordinates, so the density is
+    /// stretched by the projection.
+    fn pdf_value(&self, direction: &Vector) -> f64 {
+        let unit = direction.normalize();
+        let sin_theta = (1. - unit.y.powi(2)).max(0.).sqrt();
+        if !self.is_sampled() || sin_theta <= 0. {
+            return 0.;
+        }
+
+        let index = self.pixel_index(&unit);
+        let weight = self.cdf[index] - index.checked_sub(1).map_or(0., |i| self.cdf[i]);
+        let image_pdf = weight / self.total_weight() * f64::conv(self.cdf.len());
+        image_pdf / (2. * PI * PI * sin_theta)
+    }
+
+    fn random_direction(&self, rng: &mut UsedRng) -> Option<Vector> {
+        if !self.is_sampled() {
+            return None;
+        }
+
+        // Choose a pixel, skipping those with no weight, and then a point within it
+        let target = rng.gen::<f64>() * self.total_weight();
+        let index = self
+            .cdf
+            .partition_point(|&c| c <= target)
+            .min(self.cdf.len() - 1);
+        let size = self.image.size();
+        let u = (f64::conv(index % size.width) + rng.gen::<f64>()) / f64::conv(size.width);
+        let v = (f64::conv(index / size.width) + rng.gen::<f64>()) / f64::conv(size.height);
+
+        let theta = PI * v;
+        let phi = 2. * PI * (u - 0.5) + self.rotation;
+        Some(Vector::new(
+            theta.sin() * phi.sin(),
+            theta.cos(),
+            -theta.sin() * phi.cos(),
+        ))
+    }
+}
+
+/// The coefficients of the Perez sky luminance distribution for one channel of a sky.
+#[derive(Debug, Clone, Copy)]
+struct Perez([f64; 5]);
+impl Perez {
+    /// The coefficients for the `turbidity`, each of which is linear in it.
+    fn new(turbidity: f64, linear: [(f64, f64); 5]) -> Self {
+        Self(linear.map(|(slope, offset)| slope * turbidity + offset))
+    }
+
+    /// The relative brightness in a direction at the zenith angle `theta` and at the angle
+    /// `gamma` from the sun.
+    fn distribution(&self, theta: f64, gamma: f64) -> f64 {
+        let [a, b, c, d, e] = self.0;
+        (1. + a * (b / theta.cos()).exp()) * (1. + c * (d * gamma).exp() + e * gamma.cos().powi(2))
+    }
+}
+
+/// A clear daytime sky lit by the sun, using the analytic model of Preetham, Shirley and Smits,
+/// "A Practical Analytic Model for Daylight" (1999).
+///
+/// The sun is a small disk of the given angular diameter, which is sampled like a light so that
+/// it casts clean shadows, and its light is reddened by the atmosphere when it is low.
+/// Below the horizon the sky continues as it is at the horizon, which is usually hidden by the
+/// ground.
+#[derive(Debug, Clone)]
+pub struct Sky {
+    /// The unit direction towards the sun.
+    sun: Vector,
+    /// The angle between the zenith and the sun, which is kept above the horizon for the model.
+    sun_theta: f64,
+    /// The distributions of the luminance `Y` and the chromaticities `x` and `y`.
+    perez: [Perez; 3],
+    /// The luminance and chromaticities at the zenith.
+    zenith: [f64; 3],
+    /// The color of the sun after passing through the atmosphere.
+    sun_transmittance: Color,
+    /// One minus the cosine of the angular radius of the sun.
+    sun_size: f64,
+    intensity: f64,
+}
+impl Sky {
+    /// Luminances are in thousands of candelas per square meter, which are scaled so that a
+    /// clear sky has a brightness of roughly one.
+    const LUMINANCE_SCALE: f64 = 0.05;
+    /// The luminance of the sun seen from outside the atmosphere, in the same units as the sky.
+    const SUN_LUMINANCE: f64 = 1.6e6;
+    /// The angular diameter of the sun in degrees as seen from Earth.
+    pub const SUN_DIAMETER: f64 = 0.53;
+    /// The chance of sampling the sun rather than the rest of the sky.
+    const SUN_PROBABILITY: f64 = 0.5;
+
+    /// The sky with the sun in the direction `sun`, which should be above the horizon, with hazier
+    /// skies having a higher `turbidity`, from about 2 for a very clear sky to 10 for a hazy one.
+    pub fn new(sun: Vector, turbidity: f64) -> Self {
+        let sun = sun.normalize();
+        let sun_theta = sun.y.clamp(0., 1.).acos();
+        let t = turbidity;
+
+        // Zenith luminance and chromaticities are fitted to the turbidity and sun angle
+        let chi = (4. / 9. - t / 120.) * (PI - 2. * sun_theta);
+        let zenith_luminance = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let chromaticity = |m: [[f64; 4]; 3]| {
+            let ts = [t * t, t, 1.];
+            let thetas = [sun_theta.powi(3), sun_theta.powi(2), sun_theta, 1.];
+            (0..3)
+                .map(|i| ts[i] * (0..4).map(|j| m[i][j] * thetas[j]).sum::<f64>())
+                .sum()
+        };
+        let zenith = [
+            zenith_luminance,
+            chromaticity([
+                [0.00166, -0.00375, 0.00209, 0.],
+                [-0.02903, 0.06377, -0.03202, 0.00394],
+                [0.11693, -0.21196, 0.06052, 0.25886],
+            ]),
+            chromaticity([
+                [0.00275, -0.00610, 0.00317, 0.],
+                [-0.04214, 0.08970, -0.04153, 0.00516],
+                [0.15346, -0.26756, 0.06670, 0.26688],
+            ]),
+        ];
+        let perez = [
+            Perez::new(
+                t,
+                [
+                    (0.1787, -1.4630),
+                    (-0.3554, 0.4275),
+                    (-0.0227, 5.3251),
+                    (0.1206, -2.5771),
+                    (-0.0670, 0.3703),
+                ],
+            ),
+            Perez::new(
+                t,
+                [
+                    (-0.0193, -0.2592),
+                    (-0.0665, 0.0008),
+                    (-0.0004, 0.2125),
+                    (-0.0641, -0.8989),
+                    (-0.0033, 0.0452),
+                ],
+            ),
+            Perez::new(
+                t,
+                [
+                    (-0.0167, -0.2608),
+                    (-0.0950, 0.0092),
+                    (-0.0079, 0.2102),
+                    (-0.0441, -1.6537),
+                    (-0.0109, 0.0529),
+                ],
+            ),
+        ];
+
+        // Rayleigh scattering by air and scattering by haze at red, green and blue wavelengths
+        // in micrometers, through the air mass in the direction of the sun
+        let air_mass =
+            1. / (sun_theta.cos() + 0.15 * (93.885 - sun_theta.to_degrees()).powf(-1.253));
+        let haze = 0.04608 * t - 0.04586;
+        let sun_transmittance = Vector::new(0.65, 0.55, 0.45).map(|wavelength: f64| {
+            let depth = 0.008735 * wavelength.powf(-4.08) + haze * wavelength.powf(-1.3);
+            (-air_mass * depth).exp()
+        });
+
+        Self {
+            sun,
+            sun_theta,
+            perez,
+            zenith,
+            sun_transmittance: if sun.y > 0. {
+                sun_transmittance
+            } else {
+                Color::new(0., 0., 0.)
+            },
+            sun_size: 0.,
+            intensity: 1.,
+        }
+        .with_sun_diameter(Self::SUN_DIAMETER)
+    }
+
+    /// Changes the angular diameter of the sun in degrees, which must be greater than zero,
+    /// keeping the light it gives the same so that larger suns just cast softer shadows.
+    pub fn with_sun_diameter(mut self, degrees: f64) -> Self {
+        self.sun_size = 1. - (degrees.to_radians() / 2.).cos();
+        self
+    }
+
+    /// Scales the brightness of the sky and the sun.
+    pub fn with_intensity(mut self, intensity: f64) -> Self {
+        self.intensity = intensity;
+        self
+    }
+
+    /// The light from the sky alone in a unit direction.
+    fn sky_color(&self, unit: &Vector) -> Color {
+        // The model is only valid above the horizon
+        let theta = unit.y.clamp(1e-3, 1.).acos();
+        let gamma = unit.dot(self.sun).clamp(-1., 1.).acos();
+        let [luminance, x, y] = [0, 1, 2].map(|i| {
+            self.zenith[i] * self.perez[i].distribution(theta, gamma)
+                / self.perez[i].distribution(0., self.sun_theta)
+        });
+
+        // Convert from the xyY color space to linear sRGB
+        let luminance = luminance * Self::LUMINANCE_SCALE;
+        let (cx, cz) = (x / y * luminance, (1. - x - y) / y * luminance);
+        Color::new(
+            3.2406 * cx - 1.5372 * luminance - 0.4986 * cz,
+            -0.9689 * cx + 1.8758 * luminance + 0.0415 * cz,
+            0.0557 * cx - 0.2040 * luminance + 1.0570 * cz,
+        )
+        .map(|c| c.max(0.))
+    }
+
+    /// The light from the disk of the sun.
+    fn sun_color(&self) -> Color {
+        let standard_size = 1. - (Self::SUN_DIAMETER.to_radians() / 2.).cos();
+        self.sun_transmittance * Self::SUN_LUMINANCE * Self::LUMINANCE_SCALE * standard_size
+            / self.sun_size
+    }
+
+    /// The distribution of sampled directions, with the sun sampled only if it is visible.
+    fn sample_pdf<'a>(&self, sun: &'a ConePdf, sky: &'a CosinePdf) -> MixturePdf<'a> {
+        let sun_probability = if self.sun.y > 0. && self.sun_size > 0. {
+            Self::SUN_PROBABILITY
+        } else {
+            0.
+        };
+        MixturePdf::new(sun, sky, sun_probability)
+    }
+}
+impl Background for Sky {
+    fn color(&self, direction: &Vector) -> Color {
+        let unit = direction.normalize();
+        let mut color = self.sky_color(&unit);
+        if 1. - unit.dot(self.sun) <= self.sun_size {
+            color += self.sun_color();
+        }
+        color * self.intensity
+    }
+
+    fn is_sampled(&self) -> bool {
+        true
+    }
+
+    /// The sun is sampled as often as the rest of the sky, which is mostly above the horizon.
+    fn pdf_value(&self, direction: &Vector) -> f64 {
+        let sun = ConePdf::new(self.sun, self.sun_size);
+        let sky = CosinePdf::new(Vector::unit_y());
+        self.sample_pdf(&sun, &sky).value(direction)
+    }
+
+    fn random_direction(&self, rng: &mut UsedRng) -> Option<Vector> {
+        let sun = ConePdf::new(self.sun, self.sun_size);
+        let sky = CosinePdf::new(Vector::unit_y());
+        self.sample_pdf(&sun, &sky).generate(rng)
+    }
+}
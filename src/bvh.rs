@@ -0,0 +1,188 @@
+//! Acceleration structure for scenes with many objects.
+
+use crate::{
+    hittable::{closest_hit, HitRecord, Hittable, Light},
+    math::{Aabb, Ray},
+};
+use easy_cast::{Conv, ConvFloat};
+use std::ops::RangeInclusive;
+
+/// Number of buckets along an axis into which object centroids are binned when evaluating splits.
+const SAH_BINS: usize = 16;
+/// Cost of traversing an interior node, relative to the cost of intersecting a single object.
+const TRAVERSAL_COST: f64 = 0.125;
+/// Nodes with at most this many objects become leaves if no split is cheaper.
+const MAX_LEAF_OBJECTS: usize = 4;
+
+enum BvhContents<H> {
+    Leaf(Box<[H]>),
+    Split {
+        /// The axis along which the children were split, with the left child being lower.
+        axis: usize,
+        left: Box<BvhNode<H>>,
+        right: Box<BvhNode<H>>,
+    },
+}
+
+/// A bounding volume hierarchy, which is a tree of bounding boxes used to quickly cull objects
+/// that a ray cannot possibly hit.
+///
+/// The tree is built using the surface area heuristic (SAH) with binned centroids.
+pub struct BvhNode<H> {
+    bounding_box: Aabb,
+    contents: BvhContents<H>,
+}
+impl<H: Hittable> BvhNode<H> {
+    /// Builds the hierarchy over all of the objects.
+    pub fn new(objects: Vec<H>) -> Self {
+        let bounding_box = objects
+            .iter()
+            .fold(Aabb::empty(), |bb, o| bb.union(&o.bounding_box()));
+
+        match Self::best_split(&objects, &bounding_box) {
+            Some((axis, split_bin, centroid_box)) => {
+                let (left, right) = objects
+                    .into_iter()
+                    .partition(|o| Self::bin(o, axis, &centroid_box) <= split_bin);
+
+                Self {
+                    bounding_box,
+                    contents: BvhContents::Split {
+                        axis,
+                        left: Box::new(Self::new(left)),
+                        right: Box::new(Self::new(right)),
+                    },
+                }
+            }
+            None => Self {
+                bounding_box,
+                contents: BvhContents::Leaf(objects.into_boxed_slice()),
+            },
+        }
+    }
+
+    /// Determines the bin into which the object's centroid falls along the axis.
+    fn bin(object: &H, axis: usize, centroid_box: &Aabb) -> usize {
+        let extent = centroid_box.max[axis] - centroid_box.min[axis];
+        let offset = (object.bounding_box().centroid()[axis] - centroid_box.min[axis]) / extent;
+
+        usize::conv_floor(offset * f64::conv(SAH_BINS)).min(SAH_BINS - 1)
+    }
+
+    /// Finds the cheapest way to split the objects according to the SAH.
+    ///
+    /// Returns the split axis, the last bin in the left child, and the bounds of the object centroids,
+    /// or `None` if the objects should be kept together in a leaf.
+    fn best_split(objects: &[H], bounding_box: &Aabb) -> Option<(usize, usize, Aabb)> {
+        if objects.len() <= 1 {
+            return None;
+        }
+
+        let centroid_box = objects.iter().fold(Aabb::empty(), |bb, o| {
+            let centroid = o.bounding_box().centroid();
+            bb.union(&Aabb::new(centroid, centroid))
+        });
+
+        let mut best: Option<(f64, usize, usize)> = None;
+        for axis in 0..3 {
+            // If all the centroids coincide along this axis there is no way to split them
+            if centroid_box.max[axis] <= centroid_box.min[axis] {
+                continue;
+            }
+
+            // Gather the bounds and object counts of each bin
+            let mut bins = [(Aabb::empty(), 0usize); SAH_BINS];
+            for object in objects {
+                let bin = &mut bins[Self::bin(object, axis, &centroid_box)];
+                bin.0 = bin.0.union(&object.bounding_box());
+                bin.1 += 1;
+            }
+
+            // Sweep from the right to get the area and count to the right of each split
+            let mut right_costs = [0.; SAH_BINS];
+            let (mut right_box, mut right_count) = (Aabb::empty(), 0);
+            for split in (1..SAH_BINS).rev() {
+                right_box = right_box.union(&bins[split].0);
+                right_count += bins[split].1;
+                right_costs[split - 1] = right_box.surface_area() * f64::conv(right_count);
+            }
+
+            // Sweep from the left evaluating each split
+            let (mut left_box, mut left_count) = (Aabb::empty(), 0);
+            for split in 0..SAH_BINS - 1 {
+                left_box = left_box.union(&bins[split].0);
+                left_count += bins[split].1;
+                if left_count == 0 || left_count == objects.len() {
+                    continue;
+                }
+
+                let cost = left_box.surface_area() * f64::conv(left_count) + right_costs[split];
+                if best.is_none_or(|(c, _, _)| cost < c) {
+                    best = Some((cost, axis, split));
+                }
+            }
+        }
+
+        let (cost, axis, split) = best?;
+        let cost = TRAVERSAL_COST + cost / bounding_box.surface_area();
+        if cost < f64::conv(objects.len()) || objects.len() > MAX_LEAF_OBJECTS {
+            Some((axis, split, centroid_box))
+        } else {
+            None
+        }
+    }
+}
+impl<H: Hittable> Hittable for BvhNode<H> {
+    fn hit(&self, ray: &Ray, t_range: &RangeInclusive<f64>) -> Option<HitRecord<'_>> {
+        if !self.bounding_box.hit(ray, t_range) {
+            return None;
+        }
+
+        match &self.contents {
+            BvhContents::Leaf(objects) => closest_hit(objects.iter(), ray, t_range),
+            BvhContents::Split { axis, left, right } => {
+                // Visit the nearer child first so that the farther one can be culled by the closer hit
+                let (near, far) = if ray.direction[*axis] < 0. {
+                    (right, left)
+                } else {
+                    (left, right)
+                };
+
+                let near_hit = near.hit(ray, t_range);
+                let far_hit = far.hit(
+                    ray,
+                    &RangeInclusive::new(
+                        *t_range.start(),
+                        near_hit.as_ref().map(|hr| hr.t).unwrap_or(*t_range.end()),
+                    ),
+                );
+
+                far_hit.or(near_hit)
+            }
+        }
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bounding_box
+    }
+
+    fn collect_lights<'a>(&'a self, lights: &mut Vec<Light<'a>>) {
+        match &self.contents {
+            BvhContents::Leaf(objects) => objects.iter().for_each(|o| o.collect_lights(lights)),
+            BvhContents::Split { left, right, .. } => {
+                left.collect_lights(lights);
+                right.collect_lights(lights);
+            }
+        }
+    }
+
+    fn collect_media<'a>(&'a self, media: &mut Vec<Box<dyn Hittable + 'a>>) {
+        match &self.contents {
+            BvhContents::Leaf(objects) => objects.iter().for_each(|o| o.collect_media(media)),
+            BvhContents::Split { left, right, .. } => {
+                left.collect_media(media);
+                right.collect_media(media);
+            }
+        }
+    }
+}
@@ -1,43 +1,203 @@
+//! Positioning of the camera and rendering of images by tracing rays through each pixel.
+
 use crate::{
+    background::Background,
+    checkpoint::Fnv1a,
     hittable::Hittable,
     image::{Color, Image, Size},
-    math::{BasisVectors, DirectionVectors, Point, Ray, Vector, VectorExt},
+    integrator::{Integrator, PathTracer, World},
+    math::{BasisVectors, DirectionVectors, Point, Ray, RunningStatistics, Vector, VectorExt},
+    tile::TileOrder,
+    tonemap::LUMINANCE_WEIGHTS,
     UsedRng,
 };
-use cgmath::{ElementWise, InnerSpace, VectorSpace, Zero};
-use easy_cast::Cast;
+use cgmath::{EuclideanSpace, InnerSpace, Zero};
+use easy_cast::{Cast, Conv};
 use indicatif::{ProgressBar, ProgressStyle};
-use itertools::iproduct;
 use num::rational::Ratio;
-use rand::{thread_rng, Rng};
-use rayon::prelude::{ParallelBridge, ParallelIterator};
-use std::ops::RangeInclusive;
-
-/// The location of the focal point of the camera.
-const CAMERA_LOOK_FROM: Point = Point::new(13., 2., 3.);
-/// Point the center of the camera is aimed towards.
-const CAMERA_LOOK_AT: Point = Point::new(0., 0., 0.);
-/// Camera-relative up direction
-const CAMERA_UP_DIRECTION: Vector = Vector::new(0., 1., 0.);
-/// Vertical camera field of view in degrees.
-const CAMERA_VERTICAL_FOV: f64 = 20.;
-/// Number of random samples averaged to render a single pixel.
-const SAMPLES_PER_PIXEL: usize = 500;
-/// Variation angle of rays through each pixel in degrees.
-const DEFOCUS_ANGLE: f64 = 0.6;
-/// Distance from the camera look from point to the plane of perfect focus
-const FOCUS_DISTANCE: f64 = 10.;
-/// The maximum number of ray bounces before just being black.
-const MAX_DEPTH: usize = 50;
+use rand::{Rng, SeedableRng};
+use rayon::prelude::{IntoParallelIterator, ParallelIterator};
+use std::{hash::Hasher, sync::Arc};
+
+/// Relative noise is measured against at least this luminance, so that nearly black pixels are
+/// not sampled forever.
+const MIN_LUMINANCE: f64 = 0.01;
+/// Random numbers available to each pixel in a single pass, which is far more than are ever used.
+const PASS_RANDOM_WORDS: u128 = 1 << 48;
+
+/// Adjustable settings that determine the view and quality of the camera.
+///
+/// The defaults are those of the final scene of the first book.
+/// Settings are changed by chaining the builder methods, for example
+/// `CameraSettings::default().vertical_fov(40.).samples_per_pixel(100)`.
+#[derive(Debug, Clone)]
+pub struct CameraSettings {
+    look_from: Point,
+    look_at: Point,
+    up_direction: Vector,
+    vertical_fov: f64,
+    defocus_angle: f64,
+    focus_distance: f64,
+    shutter_open: f64,
+    shutter_close: f64,
+    samples_per_pixel: usize,
+    min_samples_per_pixel: usize,
+    noise_threshold: Option<f64>,
+    max_depth: usize,
+    integrator: Arc<dyn Integrator>,
+    seed: u64,
+    tile_size: usize,
+    tile_order: TileOrder,
+}
+impl CameraSettings {
+    /// The location of the focal point of the camera.
+    pub fn look_from(mut self, look_from: Point) -> Self {
+        self.look_from = look_from;
+        self
+    }
+
+    /// Point the center of the camera is aimed towards.
+    pub fn look_at(mut self, look_at: Point) -> Self {
+        self.look_at = look_at;
+        self
+    }
+
+    /// Camera-relative up direction
+    pub fn up_direction(mut self, up_direction: Vector) -> Self {
+        self.up_direction = up_direction;
+        self
+    }
+
+    /// Vertical camera field of view in degrees.
+    pub fn vertical_fov(mut self, vertical_fov: f64) -> Self {
+        self.vertical_fov = vertical_fov;
+        self
+    }
+
+    /// Variation angle of rays through each pixel in degrees.
+    pub fn defocus_angle(mut self, defocus_angle: f64) -> Self {
+        self.defocus_angle = defocus_angle;
+        self
+    }
 
+    /// Distance from the camera look from point to the plane of perfect focus
+    pub fn focus_distance(mut self, focus_distance: f64) -> Self {
+        self.focus_distance = focus_distance;
+        self
+    }
+
+    /// The time at which the shutter opens, with moving objects being blurred along their path
+    /// while it is open.
+    pub fn shutter_open(mut self, shutter_open: f64) -> Self {
+        self.shutter_open = shutter_open;
+        self
+    }
+
+    /// The time at which the shutter closes.
+    pub fn shutter_close(mut self, shutter_close: f64) -> Self {
+        self.shutter_close = shutter_close;
+        self
+    }
+
+    /// Number of random samples averaged to render a single pixel.
+    pub fn samples_per_pixel(mut self, samples_per_pixel: usize) -> Self {
+        self.samples_per_pixel = samples_per_pixel;
+        self
+    }
+
+    /// With adaptive sampling, the number of samples taken for every pixel before checking
+    /// whether it has converged.
+    pub fn min_samples_per_pixel(mut self, min_samples_per_pixel: usize) -> Self {
+        self.min_samples_per_pixel = min_samples_per_pixel;
+        self
+    }
+
+    /// Enables adaptive sampling, where sampling of a pixel stops once the standard error of its
+    /// mean luminance relative to the luminance falls below the threshold.
+    ///
+    /// The [`samples_per_pixel`](Self::samples_per_pixel) is then the maximum number of samples.
+    pub fn noise_threshold(mut self, noise_threshold: f64) -> Self {
+        self.noise_threshold = Some(noise_threshold);
+        self
+    }
+
+    /// The maximum number of ray bounces before just being black.
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// The algorithm used to compute the light arriving along each ray.
+    pub fn integrator(mut self, integrator: Arc<dyn Integrator>) -> Self {
+        self.integrator = integrator;
+        self
+    }
+
+    /// Seed for the random numbers used to sample each pixel, so that renders are reproducible.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Side length in pixels of the square tiles into which the image is divided for rendering,
+    /// which is at least one.
+    pub fn tile_size(mut self, tile_size: usize) -> Self {
+        self.tile_size = tile_size.max(1);
+        self
+    }
+
+    /// The order in which tiles are rendered.
+    pub fn tile_order(mut self, tile_order: TileOrder) -> Self {
+        self.tile_order = tile_order;
+        self
+    }
+}
+impl Default for CameraSettings {
+    fn default() -> Self {
+        Self {
+            look_from: Point::new(13., 2., 3.),
+            look_at: Point::new(0., 0., 0.),
+            up_direction: Vector::new(0., 1., 0.),
+            vertical_fov: 20.,
+            defocus_angle: 0.6,
+            focus_distance: 10.,
+            shutter_open: 0.,
+            shutter_close: 0.,
+            samples_per_pixel: 500,
+            min_samples_per_pixel: 16,
+            noise_threshold: None,
+            max_depth: 50,
+            integrator: Arc::new(PathTracer::default()),
+            seed: 0,
+            tile_size: 32,
+            tile_order: TileOrder::default(),
+        }
+    }
+}
+
+/// A camera ready to render images of a fixed size.
 pub struct Camera {
     image_size: Size<usize>,
+    look_from: Point,
+    defocus_angle: f64,
+    shutter_open: f64,
+    shutter_close: f64,
+    samples_per_pixel: usize,
+    min_samples_per_pixel: usize,
+    noise_threshold: Option<f64>,
+    max_depth: usize,
+    integrator: Arc<dyn Integrator>,
+    seed: u64,
+    tile_size: usize,
+    tile_order: TileOrder,
     pixel_upper_left: Point,
     pixel_delta_vectors: DirectionVectors,
     defocus_disk_basis: DirectionVectors,
 }
 impl Camera {
-    pub fn new(image_width: usize, aspect_ratio: Ratio<usize>) -> Self {
+    /// Sets up the camera for an image with the given width, with the height being determined by
+    /// the `aspect_ratio` of width to height.
+    pub fn new(image_width: usize, aspect_ratio: Ratio<usize>, settings: &CameraSettings) -> Self {
         // Calculate the image size
         let image_size = Size::new(
             image_width,
@@ -45,12 +205,13 @@ impl Camera {
         );
 
         // Determine viewport height and size.
-        let viewport_height = 2. * FOCUS_DISTANCE * (CAMERA_VERTICAL_FOV.to_radians() / 2.).tan();
+        let viewport_height =
+            2. * settings.focus_distance * (settings.vertical_fov.to_radians() / 2.).tan();
         let viewport_size = Size::new(image_size.aspect_ratio() * viewport_height, viewport_height);
 
         // Determine the camera basis vectors
-        let w = (CAMERA_LOOK_FROM - CAMERA_LOOK_AT).normalize();
-        let u = CAMERA_UP_DIRECTION.cross(w).normalize();
+        let w = (settings.look_from - settings.look_at).normalize();
+        let u = settings.up_direction.cross(w).normalize();
         let camera_frame_basis = BasisVectors::new(u, w.cross(u), w);
 
         // Set the viewport edge vectors
@@ -66,8 +227,8 @@ impl Camera {
         );
 
         // Calculate the location of the upper left of the viewport
-        let viewport_upper_left = CAMERA_LOOK_FROM
-            - FOCUS_DISTANCE * camera_frame_basis.w
+        let viewport_upper_left = settings.look_from
+            - settings.focus_distance * camera_frame_basis.w
             - viewport_edge_vectors.u / 2.
             - viewport_edge_vectors.v / 2.;
 
@@ -76,7 +237,8 @@ impl Camera {
             viewport_upper_left + 0.5 * (pixel_delta_vectors.u + pixel_delta_vectors.v);
 
         // Calculate camera defocus disk radii
-        let defocus_radius = FOCUS_DISTANCE * (DEFOCUS_ANGLE.to_radians() / 2.).tan();
+        let defocus_radius =
+            settings.focus_distance * (settings.defocus_angle.to_radians() / 2.).tan();
         let defocus_disk_basis = DirectionVectors::new(
             defocus_radius * camera_frame_basis.u,
             defocus_radius * camera_frame_basis.v,
@@ -84,47 +246,37 @@ impl Camera {
 
         Self {
             image_size,
+            look_from: settings.look_from,
+            defocus_angle: settings.defocus_angle,
+            shutter_open: settings.shutter_open,
+            shutter_close: settings.shutter_close,
+            samples_per_pixel: settings.samples_per_pixel,
+            // At least two samples are needed to estimate the noise
+            min_samples_per_pixel: settings
+                .min_samples_per_pixel
+                .clamp(2, settings.samples_per_pixel.max(2)),
+            noise_threshold: settings.noise_threshold,
+            max_depth: settings.max_depth,
+            integrator: settings.integrator.clone(),
+            seed: settings.seed,
+            tile_size: settings.tile_size,
+            tile_order: settings.tile_order,
             pixel_upper_left,
             pixel_delta_vectors,
             defocus_disk_basis,
         }
     }
 
-    fn ray_color<H: Hittable>(rng: &mut UsedRng, depth: usize, ray: &Ray, hittable: &H) -> Color {
-        // If we have recursed too much just return black
-        if depth == 0 {
-            return Color::zero();
-        }
-
-        // Did we hit something?
-        match hittable.hit(ray, &RangeInclusive::new(0.001, f64::INFINITY)) {
-            Some(hr) => {
-                // Scatter based on the material
-                let scatter = hr.material.scatter(rng, ray, &hr);
-                match scatter.ray {
-                    Some(r) => Self::ray_color(rng, depth - 1, &r, hittable)
-                        .mul_element_wise(scatter.attenuation),
-                    None => Color::zero(),
-                }
-            }
-            None => {
-                // Creates a sky-like color gradient
-                let unit = ray.direction.normalize();
-                Color::new(1., 1., 1.).lerp(Color::new(0.5, 0.7, 1.), 0.5 * (unit.y + 1.))
-            }
-        }
-    }
-
     fn get_ray(&self, rng: &mut UsedRng, pixel_center: Point) -> Ray {
         // Get a random point on the defocus disk
         let ray_origin = {
-            if DEFOCUS_ANGLE > 0. {
+            if self.defocus_angle > 0. {
                 let point = Vector::random_within_unit_disk(rng);
-                CAMERA_LOOK_FROM
+                self.look_from
                     + point.x * self.defocus_disk_basis.u
                     + point.y * self.defocus_disk_basis.v
             } else {
-                CAMERA_LOOK_FROM
+                self.look_from
             }
         };
 
@@ -132,51 +284,265 @@ impl Camera {
             + (rng.gen::<f64>() - 0.5) * self.pixel_delta_vectors.u
             + (rng.gen::<f64>() - 0.5) * self.pixel_delta_vectors.v;
 
-        Ray::new(ray_origin, pixel_sample - ray_origin)
+        // Take the ray at a random moment while the shutter is open
+        let time = self.shutter_open + rng.gen::<f64>() * (self.shutter_close - self.shutter_open);
+
+        Ray::new(ray_origin, pixel_sample - ray_origin, time)
     }
 
-    pub fn render<H: Hittable>(&self, hittable: &H) -> Image {
+    /// The seed for the random numbers used to sample each pixel.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// A hash of all of the settings that affect the rendered image, apart from the seed and how
+    /// the image is divided into tiles.
+    pub(crate) fn settings_hash(&self) -> u64 {
+        let mut hasher = Fnv1a::default();
+        for vector in [
+            self.look_from.to_vec(),
+            self.pixel_upper_left.to_vec(),
+            self.pixel_delta_vectors.u,
+            self.pixel_delta_vectors.v,
+            self.defocus_disk_basis.u,
+            self.defocus_disk_basis.v,
+        ] {
+            for x in [vector.x, vector.y, vector.z] {
+                hasher.write_u64(x.to_bits());
+            }
+        }
+        hasher.write(format!("{:?}", self.integrator).as_bytes());
+        for x in [self.defocus_angle, self.shutter_open, self.shutter_close] {
+            hasher.write_u64(x.to_bits());
+        }
+        hasher.write_u64(self.noise_threshold.map_or(u64::MAX, f64::to_bits));
+        for x in [
+            self.image_size.width,
+            self.image_size.height,
+            self.samples_per_pixel,
+            self.min_samples_per_pixel,
+            self.max_depth,
+        ] {
+            hasher.write_u64(u64::conv(x));
+        }
+
+        hasher.finish()
+    }
+
+    /// Whether the pixel needs no more samples, having either reached the maximum or converged
+    /// with adaptive sampling.
+    fn pixel_done(&self, pixel: &PixelAccumulation) -> bool {
+        pixel.samples >= self.samples_per_pixel
+            || self.noise_threshold.is_some_and(|threshold| {
+                pixel.samples >= self.min_samples_per_pixel
+                    && pixel.luminance.standard_error()
+                        <= threshold * pixel.luminance.mean().max(MIN_LUMINANCE)
+            })
+    }
+
+    /// Adds up to `samples` random samples within a single pixel to its running totals.
+    fn sample_pixel(
+        &self,
+        world: &World,
+        (x, y): (usize, usize),
+        pixel: &mut PixelAccumulation,
+        samples: usize,
+        pass: usize,
+    ) {
+        // Each pixel has its own random stream so that the result does not depend on threading,
+        // with each pass using a separate part of the stream
+        let mut rng = UsedRng::seed_from_u64(self.seed);
+        rng.set_stream(u64::conv(y * self.image_size.width + x) + 1);
+        rng.set_word_pos(u128::conv(pass) * PASS_RANDOM_WORDS);
+
+        // Project the ray from the camera through the pixel
+        let pixel_center = self.pixel_upper_left
+            + self.pixel_delta_vectors.u * x.cast()
+            + self.pixel_delta_vectors.v * y.cast();
+
+        // Accumulate random sample point colors for anti-aliasing
+        for _ in 0..samples {
+            if self.pixel_done(pixel) {
+                break;
+            }
+
+            let ray = self.get_ray(&mut rng, pixel_center);
+            let color = self
+                .integrator
+                .radiance(&mut rng, &ray, world, self.max_depth);
+            pixel.sum += color;
+            pixel.samples += 1;
+            if self.noise_threshold.is_some() {
+                pixel.luminance.push(color.dot(LUMINANCE_WEIGHTS));
+            }
+        }
+    }
+
+    /// An empty accumulation for progressively rendering images with this camera.
+    pub fn accumulation(&self) -> Accumulation {
+        Accumulation {
+            size: self.image_size,
+            pixels: vec![PixelAccumulation::default(); self.image_size.len()].into(),
+            passes: 0,
+            max_samples: self.samples_per_pixel,
+        }
+    }
+
+    /// Renders a pass over the whole image with tiles in parallel, adding up to `samples` more
+    /// samples to each pixel of the accumulation that still needs them.
+    ///
+    /// The `background` is seen wherever rays escape the scene of `hittable` objects.
+    /// The result depends on the number of samples in each pass, but is otherwise reproducible.
+    pub fn render_pass<H: Hittable>(
+        &self,
+        hittable: &H,
+        background: &dyn Background,
+        accumulation: &mut Accumulation,
+        samples: usize,
+    ) {
         let image_size = self.image_size;
+        let tiles =
+            self.tile_order
+                .split_pixels(&mut accumulation.pixels, image_size, self.tile_size);
+        let pass = accumulation.passes;
+        let world = World::new(hittable, background);
 
-        // Render the scene
-        let bar = ProgressBar::new(image_size.len().cast());
-        bar.set_message("Rendering image...");
+        let bar = ProgressBar::new(tiles.len().cast());
+        bar.set_message(format!("Rendering pass {}...", pass + 1));
         bar.set_style(
-            ProgressStyle::with_template("{msg}\n{percent}% {bar:60} [ETA: {eta_precise}]")
-                .unwrap(),
+            ProgressStyle::with_template(
+                "{msg}\n{percent}% {bar:60} {pos}/{len} tiles [ETA: {eta_precise}]",
+            )
+            .unwrap(),
         );
-        let mut pixel_data = iproduct!(0..image_size.height, 0..image_size.width)
-            .enumerate()
-            .par_bridge()
-            .map(|(i, (y, x))| {
-                bar.inc(1);
-
-                // Create or retrieve the RNG for this thread
-                let mut rng = thread_rng();
-
-                // Project the ray from the camera through the pixel
-                let pixel_center = self.pixel_upper_left
-                    + self.pixel_delta_vectors.u * x.cast()
-                    + self.pixel_delta_vectors.v * y.cast();
-
-                // Average random sample point colors for anti-aliasing
-                (
-                    i,
-                    Color::average((0..SAMPLES_PER_PIXEL).map(|_| {
-                        let ray = self.get_ray(&mut rng, pixel_center);
-                        Self::ray_color(&mut rng, MAX_DEPTH, &ray, hittable)
-                    })),
-                )
-            })
-            .collect::<Vec<_>>();
-
-        // Annoyingly, Rayon does not preserve order even when collecting, so we need to sort
-        pixel_data.sort_by_key(|t| t.0);
+        // Indexed splitting still starts the tiles in roughly the order that they are given
+        tiles.into_par_iter().for_each(|(tile, mut rows)| {
+            let pixels = rows.iter_mut().flat_map(|row| row.iter_mut());
+            for (position, pixel) in tile.pixels().zip(pixels) {
+                self.sample_pixel(&world, position, pixel, samples, pass);
+            }
+            bar.inc(1);
+        });
         bar.finish_and_clear();
 
-        Image::new(
-            self.image_size,
-            pixel_data.into_iter().map(|t| t.1).collect(),
-        )
+        accumulation.passes += 1;
+    }
+
+    /// Renders the `hittable` objects in a single pass, with the `background` being seen wherever
+    /// rays escape the scene.
+    pub fn render<H: Hittable>(&self, hittable: &H, background: &dyn Background) -> Render {
+        let mut accumulation = self.accumulation();
+        self.render_pass(
+            hittable,
+            background,
+            &mut accumulation,
+            self.samples_per_pixel,
+        );
+
+        accumulation.render()
+    }
+
+    /// Renders the `hittable` objects progressively in passes of `samples` samples per pixel until
+    /// every pixel is done, calling `on_pass` with the accumulation after each one.
+    ///
+    /// The render continues from the `accumulation`, which is usually either empty or resumed
+    /// from a [checkpoint](crate::checkpoint).
+    pub fn render_progressive<H: Hittable>(
+        &self,
+        hittable: &H,
+        background: &dyn Background,
+        mut accumulation: Accumulation,
+        samples: usize,
+        mut on_pass: impl FnMut(&Accumulation),
+    ) -> Render {
+        while !accumulation.pixels.iter().all(|p| self.pixel_done(p)) {
+            self.render_pass(hittable, background, &mut accumulation, samples);
+            on_pass(&accumulation);
+        }
+
+        accumulation.render()
+    }
+}
+
+/// The running totals of the samples taken for a pixel.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct PixelAccumulation {
+    pub sum: Color,
+    pub samples: usize,
+    /// Statistics of the luminance of the samples, which are only needed for adaptive sampling.
+    pub luminance: RunningStatistics,
+}
+impl Default for PixelAccumulation {
+    fn default() -> Self {
+        Self {
+            sum: Color::zero(),
+            samples: 0,
+            luminance: RunningStatistics::default(),
+        }
+    }
+}
+
+/// The samples taken so far for every pixel of an image, which can be added to in passes with
+/// [`Camera::render_pass`].
+#[derive(Debug, Clone)]
+pub struct Accumulation {
+    pub(crate) size: Size<usize>,
+    pub(crate) pixels: Box<[PixelAccumulation]>,
+    pub(crate) passes: usize,
+    max_samples: usize,
+}
+impl Accumulation {
+    /// The number of passes rendered so far.
+    pub fn passes(&self) -> usize {
+        self.passes
+    }
+
+    /// The image with each pixel being the average of its samples so far.
+    pub fn render(&self) -> Render {
+        Render {
+            image: Image::new(
+                self.size,
+                self.pixels
+                    .iter()
+                    .map(|p| {
+                        if p.samples > 0 {
+                            p.sum / f64::conv(p.samples)
+                        } else {
+                            Color::zero()
+                        }
+                    })
+                    .collect(),
+            ),
+            sample_counts: self.pixels.iter().map(|p| p.samples).collect(),
+            max_samples: self.max_samples,
+        }
+    }
+}
+
+/// The result of rendering, with some statistics about how it was rendered.
+#[derive(Debug)]
+pub struct Render {
+    /// The rendered image.
+    pub image: Image,
+    /// The number of samples taken for each pixel in row major order, which only varies with
+    /// adaptive sampling.
+    pub sample_counts: Box<[usize]>,
+    max_samples: usize,
+}
+impl Render {
+    /// An image showing the number of samples taken for each pixel, ranging from black for none
+    /// through red and yellow to white for the maximum.
+    pub fn sample_heatmap(&self) -> Image {
+        let max = f64::conv(self.max_samples);
+        let pixel_data = self
+            .sample_counts
+            .iter()
+            .map(|count| {
+                let x = 3. * f64::conv(*count) / max;
+                Color::new(x, x - 1., x - 2.).map(|c| c.clamp(0., 1.))
+            })
+            .collect();
+
+        Image::new(self.image.size(), pixel_data)
     }
 }
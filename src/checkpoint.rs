@@ -0,0 +1,290 @@
+//! Saving and resuming of progressive renders, so that long renders are not lost if stopped.
+//!
+//! A checkpoint stores the samples accumulated for every pixel along with the number of passes
+//! rendered, which together with the seed fully determines the random numbers of later passes.
+//! Hashes of the scene, including every file that it loads, and the camera settings are also
+//! stored along with the samples per pass, so that a render is only ever resumed with exactly
+//! what it was started with.
+
+use crate::{
+    camera::{Accumulation, Camera, PixelAccumulation},
+    image::{Color, Size},
+    math::RunningStatistics,
+};
+use easy_cast::Conv;
+use std::{
+    fs::File,
+    hash::Hasher,
+    io::{BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+};
+use thiserror::Error;
+
+/// Identifies checkpoint files.
+const MAGIC: &[u8] = b"ray-tracing checkpoint\n";
+/// Incremented whenever the format changes.
+const VERSION: u32 = 2;
+/// The number of bytes in the header, after the magic bytes and version.
+const HEADER_LENGTH: u64 = 7 * 8;
+/// The number of bytes stored for each pixel.
+const PIXEL_LENGTH: u64 = 7 * 8;
+
+/// The reason a checkpoint could not be saved or resumed.
+#[derive(Debug, Error)]
+pub enum CheckpointError {
+    #[error("could not access checkpoint file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("the file is not a checkpoint")]
+    NotCheckpoint,
+    #[error("the checkpoint has format version {0}, but only version {VERSION} is supported")]
+    UnsupportedVersion(u32),
+    #[error("the checkpoint file is incomplete")]
+    Truncated,
+    #[error("the image size has changed from {0}x{1} to {2}x{3} since the checkpoint")]
+    SizeChanged(usize, usize, usize, usize),
+    #[error("the seed has changed from {0} to {1} since the checkpoint")]
+    SeedChanged(u64, u64),
+    #[error("the scene has changed since the checkpoint")]
+    SceneChanged,
+    #[error("the camera settings have changed since the checkpoint")]
+    CameraChanged,
+    #[error("the samples per pass have changed from {0} to {1} since the checkpoint")]
+    PassSamplesChanged(usize, usize),
+}
+
+/// The 64-bit FNV-1a hash, which unlike the standard library hasher is stable between runs.
+pub(crate) struct Fnv1a(u64);
+impl Default for Fnv1a {
+    fn default() -> Self {
+        Self(0xcbf29ce484222325)
+    }
+}
+impl Hasher for Fnv1a {
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = (self.0 ^ u64::from(*byte)).wrapping_mul(0x100000001b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+/// A stable hash of some data, such as the contents of a scene file.
+pub fn hash_bytes(bytes: &[u8]) -> u64 {
+    let mut hasher = Fnv1a::default();
+    hasher.write(bytes);
+    hasher.finish()
+}
+
+/// A stable hash of the text of a scene file along with the contents of the `files` that it
+/// loads.
+pub fn hash_scene(source: &str, files: &[PathBuf]) -> std::io::Result<u64> {
+    // Prefixing the lengths keeps the boundaries between the files
+    let mut hasher = Fnv1a::default();
+    let mut write = |contents: &[u8]| {
+        hasher.write(&u64::conv(contents.len()).to_le_bytes());
+        hasher.write(contents);
+    };
+    write(source.as_bytes());
+    for path in files {
+        write(&std::fs::read(path)?);
+    }
+    Ok(hasher.finish())
+}
+
+/// Saves the accumulation of a progressive render by the camera of the scene with the hash, in
+/// passes of `pass_samples` samples.
+///
+/// The checkpoint is written to a temporary file first, so that an existing checkpoint is never
+/// left incomplete if this is interrupted.
+pub fn save_checkpoint(
+    path: &Path,
+    camera: &Camera,
+    scene_hash: u64,
+    pass_samples: usize,
+    accumulation: &Accumulation,
+) -> Result<(), CheckpointError> {
+    let temporary_path = path.with_extension("tmp");
+    let mut writer = BufWriter::new(File::create(&temporary_path)?);
+
+    // Header
+    writer.write_all(MAGIC)?;
+    writer.write_all(&VERSION.to_le_bytes())?;
+    for x in [
+        camera.seed(),
+        scene_hash,
+        camera.settings_hash(),
+        u64::conv(accumulation.size.width),
+        u64::conv(accumulation.size.height),
+        u64::conv(accumulation.passes),
+        u64::conv(pass_samples),
+    ] {
+        writer.write_all(&x.to_le_bytes())?;
+    }
+
+    // Pixels
+    for pixel in accumulation.pixels.iter() {
+        for x in [
+            pixel.sum.x.to_bits(),
+            pixel.sum.y.to_bits(),
+            pixel.sum.z.to_bits(),
+            u64::conv(pixel.samples),
+            u64::conv(pixel.luminance.count),
+            pixel.luminance.mean.to_bits(),
+            pixel.luminance.squared_deviations.to_bits(),
+        ] {
+            writer.write_all(&x.to_le_bytes())?;
+        }
+    }
+
+    writer.flush()?;
+    drop(writer);
+    std::fs::rename(temporary_path, path)?;
+
+    Ok(())
+}
+
+/// A saved progressive render, which can be resumed once the scene and camera are set up again.
+pub struct Checkpoint {
+    seed: u64,
+    scene_hash: u64,
+    camera_hash: u64,
+    size: Size<usize>,
+    passes: usize,
+    pass_samples: usize,
+    pixels: Box<[PixelAccumulation]>,
+}
+impl Checkpoint {
+    /// Reads a checkpoint file written by [`save_checkpoint`].
+    pub fn load(path: &Path) -> Result<Self, CheckpointError> {
+        let file = File::open(path)?;
+        let file_length = file.metadata()?.len();
+        let reader = &mut BufReader::new(file);
+
+        // Header
+        let mut magic = [0; MAGIC.len()];
+        read_bytes(reader, &mut magic).map_err(|_| CheckpointError::NotCheckpoint)?;
+        if magic != MAGIC {
+            return Err(CheckpointError::NotCheckpoint);
+        }
+        let mut version = [0; 4];
+        read_bytes(reader, &mut version)?;
+        let version = u32::from_le_bytes(version);
+        if version != VERSION {
+            return Err(CheckpointError::UnsupportedVersion(version));
+        }
+        let seed = read_u64(reader)?;
+        let scene_hash = read_u64(reader)?;
+        let camera_hash = read_u64(reader)?;
+        let size = Size::new(
+            usize::conv(read_u64(reader)?),
+            usize::conv(read_u64(reader)?),
+        );
+        let passes = usize::conv(read_u64(reader)?);
+        let pass_samples = usize::conv(read_u64(reader)?);
+
+        // A corrupt size must not cause a huge allocation, so the pixels must fit in the file
+        let pixel_count = size
+            .width
+            .checked_mul(size.height)
+            .ok_or(CheckpointError::NotCheckpoint)?;
+        let pixels_length = file_length - (u64::conv(MAGIC.len()) + 4 + HEADER_LENGTH);
+        if u64::conv(pixel_count)
+            .checked_mul(PIXEL_LENGTH)
+            .is_none_or(|length| length > pixels_length)
+        {
+            return Err(CheckpointError::Truncated);
+        }
+
+        // Pixels
+        let pixels = (0..pixel_count)
+            .map(|_| {
+                Ok(PixelAccumulation {
+                    sum: Color::new(read_f64(reader)?, read_f64(reader)?, read_f64(reader)?),
+                    samples: usize::conv(read_u64(reader)?),
+                    luminance: RunningStatistics {
+                        count: usize::conv(read_u64(reader)?),
+                        mean: read_f64(reader)?,
+                        squared_deviations: read_f64(reader)?,
+                    },
+                })
+            })
+            .collect::<Result<_, CheckpointError>>()?;
+
+        Ok(Self {
+            seed,
+            scene_hash,
+            camera_hash,
+            size,
+            passes,
+            pass_samples,
+            pixels,
+        })
+    }
+
+    /// The seed that the render was started with, which is needed to set up the scene and camera
+    /// again.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// The accumulation to continue rendering from, ensuring that it was rendered with the same
+    /// camera settings, scene and samples per pass.
+    pub fn resume(
+        self,
+        camera: &Camera,
+        scene_hash: u64,
+        pass_samples: usize,
+    ) -> Result<Accumulation, CheckpointError> {
+        let mut accumulation = camera.accumulation();
+        if (self.size.width, self.size.height)
+            != (accumulation.size.width, accumulation.size.height)
+        {
+            return Err(CheckpointError::SizeChanged(
+                self.size.width,
+                self.size.height,
+                accumulation.size.width,
+                accumulation.size.height,
+            ));
+        }
+        if self.seed != camera.seed() {
+            return Err(CheckpointError::SeedChanged(self.seed, camera.seed()));
+        }
+        if self.scene_hash != scene_hash {
+            return Err(CheckpointError::SceneChanged);
+        }
+        if self.camera_hash != camera.settings_hash() {
+            return Err(CheckpointError::CameraChanged);
+        }
+        if self.pass_samples != pass_samples {
+            return Err(CheckpointError::PassSamplesChanged(
+                self.pass_samples,
+                pass_samples,
+            ));
+        }
+
+        accumulation.pixels = self.pixels;
+        accumulation.passes = self.passes;
+        Ok(accumulation)
+    }
+}
+
+/// Reads exactly enough bytes to fill the buffer, with running out being a truncated checkpoint.
+fn read_bytes(reader: &mut impl Read, buffer: &mut [u8]) -> Result<(), CheckpointError> {
+    reader.read_exact(buffer).map_err(|e| match e.kind() {
+        std::io::ErrorKind::UnexpectedEof => CheckpointError::Truncated,
+        _ => CheckpointError::Io(e),
+    })
+}
+
+fn read_u64(reader: &mut impl Read) -> Result<u64, CheckpointError> {
+    let mut bytes = [0; 8];
+    read_bytes(reader, &mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn read_f64(reader: &mut impl Read) -> Result<f64, CheckpointError> {
+    read_u64(reader).map(f64::from_bits)
+}
@@ -1,12 +1,20 @@
+//! Objects that can be hit by rays.
+
 use crate::{
     material::Material,
-    math::{Parabola, ParabolaRoots, Point, Ray, Vector},
+    math::{Aabb, BasisVectors, ConePdf, Matrix, Parabola, ParabolaRoots, Pdf, Point, Ray, Vector},
+    UsedRng,
 };
-use cgmath::InnerSpace;
+use cgmath::{InnerSpace, Matrix as _, SquareMatrix, Transform};
 use derive_new::new;
-use std::ops::RangeInclusive;
+use itertools::iproduct;
+use rand::Rng;
+use std::{f64::consts::PI, ops::RangeInclusive, sync::Arc};
 
-#[derive(Debug)]
+/// Rays are started slightly away from surfaces to avoid hitting the surface they left.
+pub(crate) const MIN_HIT_DISTANCE: f64 = 0.001;
+
+/// Where and how a ray hit an object.
 pub struct HitRecord<'a> {
     pub point: Point,
     // This normal always faces against the ray.
@@ -15,9 +23,22 @@ pub struct HitRecord<'a> {
     // The front face was hit.
     pub front_face: bool,
     pub material: &'a dyn Material,
+    /// The primitive object that was hit, such as a sphere or triangle, which is used to tell
+    /// which light was hit.
+    pub object: &'a dyn Hittable,
+    // Surface coordinates of the hit point, each in the range [0, 1].
+    pub u: f64,
+    pub v: f64,
 }
 impl<'a> HitRecord<'a> {
-    fn new(material: &'a dyn Material, ray: &Ray, t: f64, outward_normal: Vector) -> Self {
+    pub(crate) fn new(
+        object: &'a dyn Hittable,
+        material: &'a dyn Material,
+        ray: &Ray,
+        t: f64,
+        outward_normal: Vector,
+        (u, v): (f64, f64),
+    ) -> Self {
         let front_face = ray.direction.dot(outward_normal) < 0.;
 
         HitRecord {
@@ -30,48 +51,276 @@ impl<'a> HitRecord<'a> {
             t,
             front_face,
             material,
+            object,
+            u,
+            v,
         }
     }
 }
 
+/// An object that emits light and can be sampled directly.
+pub struct Light<'a> {
+    /// The light as placed in the scene, which is sampled and may transform the `object`.
+    pub sampler: Box<dyn Hittable + 'a>,
+    /// The primitive object that emits the light, which is the object of hits on it.
+    pub object: &'a dyn Hittable,
+}
+impl<'a> Light<'a> {
+    /// The primitive `object` sampled as it is.
+    pub fn new(object: &'a dyn Hittable) -> Self {
+        Self {
+            sampler: Box::new(object),
+            object,
+        }
+    }
+
+    /// Whether the ray first hits this placement of the light at exactly the parameter `t`, which
+    /// tells apart placements of the same object, since rays are transformed the same way when
+    /// hitting the light through the world as through the sampler.
+    pub(crate) fn hits_at(&self, ray: &Ray, t: f64) -> bool {
+        self.sampler
+            .hit(ray, &(MIN_HIT_DISTANCE..=f64::INFINITY))
+            .is_some_and(|hr| hr.t == t)
+    }
+}
+
+/// Geometry that rays can hit.
 pub trait Hittable: Sync {
-    fn hit(&self, ray: &Ray, t_range: &RangeInclusive<f64>) -> Option<HitRecord>;
+    /// The closest hit of the ray with the object, if any, with a ray parameter in the `t_range`.
+    fn hit(&self, ray: &Ray, t_range: &RangeInclusive<f64>) -> Option<HitRecord<'_>>;
+
+    /// A box that fully contains the object.
+    fn bounding_box(&self) -> Aabb;
+
+    /// The probability density with respect to solid angle of [`Hittable::random_direction`]
+    /// choosing the `direction` from the `origin`, which is zero if the object is missed.
+    ///
+    /// Only objects that stay still are sampled as lights, so this does not depend on the time.
+    fn pdf_value(&self, _origin: &Point, _direction: &Vector) -> f64 {
+        0.
+    }
+
+    /// A random direction from the `origin` towards the object, for objects that can be sampled
+    /// as lights.
+    fn random_direction(&self, _rng: &mut UsedRng, _origin: &Point) -> Option<Vector> {
+        None
+    }
+
+    /// Adds the parts of the object that emit light and can be sampled to the list of lights.
+    fn collect_lights<'a>(&'a self, _lights: &mut Vec<Light<'a>>) {}
+
+    /// A random point with a ray parameter in the `t_range` at which the ray is scattered or
+    /// absorbed, for volumes that rays pass through instead of hitting.
+    fn sample_medium(
+        &self,
+        _rng: &mut UsedRng,
+        _ray: &Ray,
+        _t_range: &RangeInclusive<f64>,
+    ) -> Option<HitRecord<'_>> {
+        None
+    }
+
+    /// Adds the volumes within the object to the list of media, which are sampled along every ray.
+    fn collect_media<'a>(&'a self, _media: &mut Vec<Box<dyn Hittable + 'a>>) {}
 }
 
-#[derive(new)]
-pub struct HittableList<'a> {
-    list: &'a [&'a dyn Hittable],
-}
-impl Hittable for HittableList<'_> {
-    fn hit(&self, ray: &Ray, t_range: &RangeInclusive<f64>) -> Option<HitRecord> {
-        self.list.iter().fold(None, |current, next| {
-            let next = next.hit(
-                ray,
-                &RangeInclusive::new(
-                    *t_range.start(),
-                    current.as_ref().map(|hr| hr.t).unwrap_or(*t_range.end()),
-                ),
-            );
-
-            next.or(current)
-        })
+impl<H: Hittable + ?Sized> Hittable for Box<H> {
+    fn hit(&self, ray: &Ray, t_range: &RangeInclusive<f64>) -> Option<HitRecord<'_>> {
+        self.as_ref().hit(ray, t_range)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.as_ref().bounding_box()
+    }
+
+    fn pdf_value(&self, origin: &Point, direction: &Vector) -> f64 {
+        self.as_ref().pdf_value(origin, direction)
+    }
+
+    fn random_direction(&self, rng: &mut UsedRng, origin: &Point) -> Option<Vector> {
+        self.as_ref().random_direction(rng, origin)
+    }
+
+    fn collect_lights<'a>(&'a self, lights: &mut Vec<Light<'a>>) {
+        self.as_ref().collect_lights(lights)
+    }
+
+    fn sample_medium(
+        &self,
+        rng: &mut UsedRng,
+        ray: &Ray,
+        t_range: &RangeInclusive<f64>,
+    ) -> Option<HitRecord<'_>> {
+        self.as_ref().sample_medium(rng, ray, t_range)
+    }
+
+    fn collect_media<'a>(&'a self, media: &mut Vec<Box<dyn Hittable + 'a>>) {
+        self.as_ref().collect_media(media)
     }
 }
 
+impl<H: Hittable + ?Sized> Hittable for &H {
+    fn hit(&self, ray: &Ray, t_range: &RangeInclusive<f64>) -> Option<HitRecord<'_>> {
+        (**self).hit(ray, t_range)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        (**self).bounding_box()
+    }
+
+    fn pdf_value(&self, origin: &Point, direction: &Vector) -> f64 {
+        (**self).pdf_value(origin, direction)
+    }
+
+    fn random_direction(&self, rng: &mut UsedRng, origin: &Point) -> Option<Vector> {
+        (**self).random_direction(rng, origin)
+    }
+
+    fn collect_lights<'a>(&'a self, lights: &mut Vec<Light<'a>>) {
+        (**self).collect_lights(lights)
+    }
+
+    fn sample_medium(
+        &self,
+        rng: &mut UsedRng,
+        ray: &Ray,
+        t_range: &RangeInclusive<f64>,
+    ) -> Option<HitRecord<'_>> {
+        (**self).sample_medium(rng, ray, t_range)
+    }
+
+    fn collect_media<'a>(&'a self, media: &mut Vec<Box<dyn Hittable + 'a>>) {
+        (**self).collect_media(media)
+    }
+}
+
+/// Shared objects can be placed in a scene many times with [`Transformed`], without copying them.
+impl<H: Hittable + Send + ?Sized> Hittable for Arc<H> {
+    fn hit(&self, ray: &Ray, t_range: &RangeInclusive<f64>) -> Option<HitRecord<'_>> {
+        self.as_ref().hit(ray, t_range)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.as_ref().bounding_box()
+    }
+
+    fn pdf_value(&self, origin: &Point, direction: &Vector) -> f64 {
+        self.as_ref().pdf_value(origin, direction)
+    }
+
+    fn random_direction(&self, rng: &mut UsedRng, origin: &Point) -> Option<Vector> {
+        self.as_ref().random_direction(rng, origin)
+    }
+
+    fn collect_lights<'a>(&'a self, lights: &mut Vec<Light<'a>>) {
+        self.as_ref().collect_lights(lights)
+    }
+
+    fn sample_medium(
+        &self,
+        rng: &mut UsedRng,
+        ray: &Ray,
+        t_range: &RangeInclusive<f64>,
+    ) -> Option<HitRecord<'_>> {
+        self.as_ref().sample_medium(rng, ray, t_range)
+    }
+
+    fn collect_media<'a>(&'a self, media: &mut Vec<Box<dyn Hittable + 'a>>) {
+        self.as_ref().collect_media(media)
+    }
+}
+
+/// Finds the closest hit among all of the objects by testing every one of them.
+pub(crate) fn closest_hit<'a, H: Hittable + 'a>(
+    objects: impl IntoIterator<Item = &'a H>,
+    ray: &Ray,
+    t_range: &RangeInclusive<f64>,
+) -> Option<HitRecord<'a>> {
+    objects.into_iter().fold(None, |current, next| {
+        let next = next.hit(
+            ray,
+            &RangeInclusive::new(
+                *t_range.start(),
+                current.as_ref().map(|hr| hr.t).unwrap_or(*t_range.end()),
+            ),
+        );
+
+        next.or(current)
+    })
+}
+
+/// The ray parameter in the `t_range` at which the ray crosses the plane through the `point` with
+/// the `normal`, if any.
+fn plane_hit(
+    point: &Point,
+    normal: &Vector,
+    ray: &Ray,
+    t_range: &RangeInclusive<f64>,
+) -> Option<f64> {
+    let denominator = normal.dot(ray.direction);
+    // Rays parallel to the plane never cross it
+    if denominator.abs() < 1e-12 {
+        return None;
+    }
+
+    let t = normal.dot(point - ray.origin) / denominator;
+    t_range.contains(&t).then_some(t)
+}
+
+/// Converts a uniform density over the `area` of a flat surface with the unit `normal` to a
+/// density over solid angle, for a `direction` that hits it at the ray parameter `t`.
+fn area_to_solid_angle(area: f64, normal: &Vector, direction: &Vector, t: f64) -> f64 {
+    let distance_squared = (t * direction.magnitude()).powi(2);
+    let cosine = normal.dot(direction.normalize()).abs();
+    distance_squared / (cosine * area)
+}
+
+/// A sphere, with surface coordinates being its longitude and latitude.
 #[derive(new)]
 pub struct Sphere {
     center: Point,
     radius: f64,
-    material: Box<dyn Material + Sync>,
+    material: Arc<dyn Material>,
 }
-impl Hittable for Sphere {
-    fn hit(&self, ray: &Ray, t_range: &RangeInclusive<f64>) -> Option<HitRecord> {
-        let oc = ray.origin - self.center;
+impl Sphere {
+    /// The spherical coordinates of a point on the unit sphere, scaled into the range `[0, 1]`.
+    ///
+    /// The `u` coordinate is the angle around the y-axis starting from -x, and
+    /// the `v` coordinate is the angle up from -y.
+    fn surface_coordinates(point: Vector) -> (f64, f64) {
+        let theta = (-point.y).clamp(-1., 1.).acos();
+        let phi = (-point.z).atan2(point.x) + PI;
+
+        (phi / (2. * PI), theta / PI)
+    }
+
+    /// One minus the cosine of the half angle of the cone of directions from the `origin` that
+    /// hit the sphere, which covers every direction from inside the sphere.
+    fn cone_size(&self, origin: &Point) -> f64 {
+        let sine_squared = self.radius.powi(2) / (self.center - origin).magnitude2();
+        if sine_squared >= 1. {
+            2.
+        } else {
+            // Avoid cancellation for small distant spheres
+            sine_squared / (1. + (1. - sine_squared).sqrt())
+        }
+    }
+
+    /// The closest hit of the ray with the sphere of the `object` at the `center`.
+    fn hit_centered<'a>(
+        object: &'a dyn Hittable,
+        center: Point,
+        radius: f64,
+        material: &'a dyn Material,
+        ray: &Ray,
+        t_range: &RangeInclusive<f64>,
+    ) -> Option<HitRecord<'a>> {
+        let oc = ray.origin - center;
 
         match Parabola::new(
             ray.direction.magnitude2(),
             2. * oc.dot(ray.direction),
-            oc.magnitude2() - self.radius.powi(2),
+            oc.magnitude2() - radius.powi(2),
         )
         .roots()
         {
@@ -82,11 +331,14 @@ impl Hittable for Sphere {
         .and_then(|rs| {
             for t in rs {
                 if t_range.contains(&t) {
+                    let outward_normal = (ray.at(t) - center) / radius;
                     return Some(HitRecord::new(
-                        self.material.as_ref(),
+                        object,
+                        material,
                         ray,
                         t,
-                        (ray.at(t) - self.center) / self.radius,
+                        outward_normal,
+                        Self::surface_coordinates(outward_normal),
                     ));
                 }
             }
@@ -94,3 +346,662 @@ impl Hittable for Sphere {
         })
     }
 }
+impl Hittable for Sphere {
+    fn hit(&self, ray: &Ray, t_range: &RangeInclusive<f64>) -> Option<HitRecord<'_>> {
+        Self::hit_centered(
+            self,
+            self.center,
+            self.radius,
+            self.material.as_ref(),
+            ray,
+            t_range,
+        )
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        let radius = Vector::new(self.radius, self.radius, self.radius);
+        Aabb::from_corners(self.center - radius, self.center + radius)
+    }
+
+    fn pdf_value(&self, origin: &Point, direction: &Vector) -> f64 {
+        if self
+            .hit(
+                &Ray::new(*origin, *direction, 0.),
+                &(MIN_HIT_DISTANCE..=f64::INFINITY),
+            )
+            .is_some()
+        {
+            1. / (2. * PI * self.cone_size(origin))
+        } else {
+            0.
+        }
+    }
+
+    /// Samples directions uniformly within the cone of directions that hit the sphere.
+    fn random_direction(&self, rng: &mut UsedRng, origin: &Point) -> Option<Vector> {
+        ConePdf::new(self.center - origin, self.cone_size(origin)).generate(rng)
+    }
+
+    fn collect_lights<'a>(&'a self, lights: &mut Vec<Light<'a>>) {
+        if self.material.is_emissive() {
+            lights.push(Light::new(self));
+        }
+    }
+}
+
+/// A single triangle, optionally with smooth shading normals and surface coordinates given at each vertex.
+#[derive(Debug)]
+pub struct Triangle {
+    /// The front face is the side from which the vertices appear counter-clockwise.
+    vertices: [Point; 3],
+    normals: Option<[Vector; 3]>,
+    surface_coordinates: Option<[(f64, f64); 3]>,
+    material: Arc<dyn Material>,
+}
+impl Triangle {
+    /// A triangle with flat shading and barycentric surface coordinates.
+    pub fn new(vertices: [Point; 3], material: Arc<dyn Material>) -> Self {
+        Self {
+            vertices,
+            normals: None,
+            surface_coordinates: None,
+            material,
+        }
+    }
+
+    /// Sets vertex normals, which are interpolated across the triangle for smooth shading.
+    pub fn with_normals(mut self, normals: [Vector; 3]) -> Self {
+        self.normals = Some(normals.map(|n| n.normalize()));
+        self
+    }
+
+    /// Sets vertex surface coordinates, which are interpolated across the triangle for texturing.
+    ///
+    /// Without these, the surface coordinates are the barycentric coordinates of the second and
+    /// third vertices.
+    pub fn with_surface_coordinates(mut self, surface_coordinates: [(f64, f64); 3]) -> Self {
+        self.surface_coordinates = Some(surface_coordinates);
+        self
+    }
+}
+impl Hittable for Triangle {
+    /// Uses the watertight intersection algorithm of Woop, Benthin and Wald (2013), so that rays
+    /// cannot slip through the shared edges of adjacent triangles.
+    fn hit(&self, ray: &Ray, t_range: &RangeInclusive<f64>) -> Option<HitRecord<'_>> {
+        // Permute the axes so that the ray direction is largest along z
+        let direction = ray.direction.map(f64::abs);
+        let kz = if direction.x > direction.y {
+            if direction.x > direction.z {
+                0
+            } else {
+                2
+            }
+        } else if direction.y > direction.z {
+            1
+        } else {
+            2
+        };
+        let (mut kx, mut ky) = ((kz + 1) % 3, (kz + 2) % 3);
+        // Preserve the winding direction
+        if ray.direction[kz] < 0. {
+            std::mem::swap(&mut kx, &mut ky);
+        }
+
+        // Shear the vertices so that the ray is along z starting at the origin
+        let shear = Vector::new(
+            ray.direction[kx] / ray.direction[kz],
+            ray.direction[ky] / ray.direction[kz],
+            1. / ray.direction[kz],
+        );
+        let [a, b, c] = self.vertices.map(|v| {
+            let v = v - ray.origin;
+            Vector::new(
+                v[kx] - shear.x * v[kz],
+                v[ky] - shear.y * v[kz],
+                shear.z * v[kz],
+            )
+        });
+
+        // Scaled barycentric coordinates from the edge functions
+        let u = c.x * b.y - c.y * b.x;
+        let v = a.x * c.y - a.y * c.x;
+        let w = b.x * a.y - b.y * a.x;
+        if (u < 0. || v < 0. || w < 0.) && (u > 0. || v > 0. || w > 0.) {
+            return None;
+        }
+
+        let determinant = u + v + w;
+        if determinant == 0. {
+            return None;
+        }
+
+        let t = (u * a.z + v * b.z + w * c.z) / determinant;
+        if !t_range.contains(&t) {
+            return None;
+        }
+
+        let barycentric = [u, v, w].map(|x| x / determinant);
+        let interpolate = |values: [Vector; 3]| {
+            barycentric[0] * values[0] + barycentric[1] * values[1] + barycentric[2] * values[2]
+        };
+
+        let [p0, p1, p2] = self.vertices;
+        let geometric_normal = (p1 - p0).cross(p2 - p0).normalize();
+        let outward_normal = match self.normals {
+            Some(normals) => {
+                // The shading normal should be on the same side as the actual surface
+                let normal = interpolate(normals).normalize();
+                if normal.dot(geometric_normal) < 0. {
+                    -normal
+                } else {
+                    normal
+                }
+            }
+            None => geometric_normal,
+        };
+
+        let surface_coordinates = match self.surface_coordinates {
+            Some(coordinates) => {
+                let uv = interpolate(coordinates.map(|(u, v)| Vector::new(u, v, 0.)));
+                (uv.x, uv.y)
+            }
+            None => (barycentric[1], barycentric[2]),
+        };
+
+        Some(HitRecord::new(
+            self,
+            self.material.as_ref(),
+            ray,
+            t,
+            outward_normal,
+            surface_coordinates,
+        ))
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        let [p0, p1, p2] = self.vertices;
+        Aabb::from_corners(p0, p1)
+            .union(&Aabb::from_corners(p1, p2))
+            .padded()
+    }
+
+    fn pdf_value(&self, origin: &Point, direction: &Vector) -> f64 {
+        let Some(hit) = self.hit(
+            &Ray::new(*origin, *direction, 0.),
+            &(MIN_HIT_DISTANCE..=f64::INFINITY),
+        ) else {
+            return 0.;
+        };
+
+        let [p0, p1, p2] = self.vertices;
+        let area_normal = 0.5 * (p1 - p0).cross(p2 - p0);
+        area_to_solid_angle(
+            area_normal.magnitude(),
+            &area_normal.normalize(),
+            direction,
+            hit.t,
+        )
+    }
+
+    /// Samples points uniformly over the area of the triangle.
+    fn random_direction(&self, rng: &mut UsedRng, origin: &Point) -> Option<Vector> {
+        let [p0, p1, p2] = self.vertices;
+        let root = rng.gen::<f64>().sqrt();
+        let split = rng.gen::<f64>();
+        let (b1, b2) = (root * (1. - split), root * split);
+
+        Some(p0 + b1 * (p1 - p0) + b2 * (p2 - p0) - origin)
+    }
+
+    fn collect_lights<'a>(&'a self, lights: &mut Vec<Light<'a>>) {
+        if self.material.is_emissive() {
+            lights.push(Light::new(self));
+        }
+    }
+}
+
+/// A sphere moving in a straight line from `start` at time zero to `end` at time one, which stays
+/// at the ends at other times.
+#[derive(new)]
+pub struct MovingSphere {
+    start: Point,
+    end: Point,
+    radius: f64,
+    material: Arc<dyn Material>,
+}
+impl MovingSphere {
+    /// Where the center is at the time.
+    fn center(&self, time: f64) -> Point {
+        self.start + time.clamp(0., 1.) * (self.end - self.start)
+    }
+}
+impl Hittable for MovingSphere {
+    fn hit(&self, ray: &Ray, t_range: &RangeInclusive<f64>) -> Option<HitRecord<'_>> {
+        Sphere::hit_centered(
+            self,
+            self.center(ray.time),
+            self.radius,
+            self.material.as_ref(),
+            ray,
+            t_range,
+        )
+    }
+
+    /// The box covers the whole path, since the time of the rays is not known in advance.
+    fn bounding_box(&self) -> Aabb {
+        let radius = Vector::new(self.radius, self.radius, self.radius);
+        Aabb::from_corners(self.start - radius, self.start + radius)
+            .union(&Aabb::from_corners(self.end - radius, self.end + radius))
+    }
+}
+
+/// A parallelogram with one corner at `corner` and sides `u` and `v`, with surface coordinates
+/// being the fractions of the way along each side.
+///
+/// The front face is the side from which `v` is counter-clockwise from `u`.
+pub struct Quad {
+    corner: Point,
+    u: Vector,
+    v: Vector,
+    normal: Vector,
+    /// Used to find the coordinates of points along the sides.
+    w: Vector,
+    area: f64,
+    material: Arc<dyn Material>,
+}
+impl Quad {
+    /// A quad made of the `material`, which is flat shaded.
+    pub fn new(corner: Point, u: Vector, v: Vector, material: Arc<dyn Material>) -> Self {
+        let n = u.cross(v);
+
+        Self {
+            corner,
+            u,
+            v,
+            normal: n.normalize(),
+            w: n / n.magnitude2(),
+            area: n.magnitude(),
+            material,
+        }
+    }
+}
+impl Hittable for Quad {
+    fn hit(&self, ray: &Ray, t_range: &RangeInclusive<f64>) -> Option<HitRecord<'_>> {
+        let t = plane_hit(&self.corner, &self.normal, ray, t_range)?;
+
+        // Find the coordinates along the sides, which must both be within the sides
+        let offset = ray.at(t) - self.corner;
+        let alpha = self.w.dot(offset.cross(self.v));
+        let beta = self.w.dot(self.u.cross(offset));
+        if !(0. ..=1.).contains(&alpha) || !(0. ..=1.).contains(&beta) {
+            return None;
+        }
+
+        Some(HitRecord::new(
+            self,
+            self.material.as_ref(),
+            ray,
+            t,
+            self.normal,
+            (alpha, beta),
+        ))
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        Aabb::from_corners(self.corner, self.corner + self.u + self.v)
+            .union(&Aabb::from_corners(
+                self.corner + self.u,
+                self.corner + self.v,
+            ))
+            .padded()
+    }
+
+    fn pdf_value(&self, origin: &Point, direction: &Vector) -> f64 {
+        self.hit(
+            &Ray::new(*origin, *direction, 0.),
+            &(MIN_HIT_DISTANCE..=f64::INFINITY),
+        )
+        .map_or(0., |hit| {
+            area_to_solid_angle(self.area, &self.normal, direction, hit.t)
+        })
+    }
+
+    /// Samples points uniformly over the area of the quad.
+    fn random_direction(&self, rng: &mut UsedRng, origin: &Point) -> Option<Vector> {
+        Some(self.corner + rng.gen::<f64>() * self.u + rng.gen::<f64>() * self.v - origin)
+    }
+
+    fn collect_lights<'a>(&'a self, lights: &mut Vec<Light<'a>>) {
+        if self.material.is_emissive() {
+            lights.push(Light::new(self));
+        }
+    }
+}
+
+/// A flat circle, with surface coordinates being the position within the square around it.
+///
+/// The front face is the side that the `normal` points out of.
+pub struct Disk {
+    center: Point,
+    radius: f64,
+    /// Vectors along the plane of the disk, and its normal.
+    basis: BasisVectors,
+    material: Arc<dyn Material>,
+}
+impl Disk {
+    /// A disk facing along the `normal`, which need not be a unit vector.
+    pub fn new(center: Point, normal: Vector, radius: f64, material: Arc<dyn Material>) -> Self {
+        Self {
+            center,
+            radius,
+            basis: BasisVectors::from_w(normal),
+            material,
+        }
+    }
+}
+impl Hittable for Disk {
+    fn hit(&self, ray: &Ray, t_range: &RangeInclusive<f64>) -> Option<HitRecord<'_>> {
+        let t = plane_hit(&self.center, &self.basis.w, ray, t_range)?;
+
+        let offset = ray.at(t) - self.center;
+        if offset.magnitude2() > self.radius.powi(2) {
+            return None;
+        }
+
+        let scale = 0.5 / self.radius;
+        Some(HitRecord::new(
+            self,
+            self.material.as_ref(),
+            ray,
+            t,
+            self.basis.w,
+            (
+                0.5 + scale * offset.dot(self.basis.u),
+                0.5 + scale * offset.dot(self.basis.v),
+            ),
+        ))
+    }
+
+    /// The disk extends less far along the axes closer to its normal.
+    fn bounding_box(&self) -> Aabb {
+        let extent = self
+            .basis
+            .w
+            .map(|n| self.radius * (1. - n * n).max(0.).sqrt());
+        Aabb::from_corners(self.center - extent, self.center + extent).padded()
+    }
+
+    fn pdf_value(&self, origin: &Point, direction: &Vector) -> f64 {
+        self.hit(
+            &Ray::new(*origin, *direction, 0.),
+            &(MIN_HIT_DISTANCE..=f64::INFINITY),
+        )
+        .map_or(0., |hit| {
+            area_to_solid_angle(PI * self.radius.powi(2), &self.basis.w, direction, hit.t)
+        })
+    }
+
+    /// Samples points uniformly over the area of the disk.
+    fn random_direction(&self, rng: &mut UsedRng, origin: &Point) -> Option<Vector> {
+        let radius = self.radius * rng.gen::<f64>().sqrt();
+        let phi = 2. * PI * rng.gen::<f64>();
+        let offset =
+            self.basis
+                .to_standard(Vector::new(radius * phi.cos(), radius * phi.sin(), 0.));
+
+        Some(self.center + offset - origin)
+    }
+
+    fn collect_lights<'a>(&'a self, lights: &mut Vec<Light<'a>>) {
+        if self.material.is_emissive() {
+            lights.push(Light::new(self));
+        }
+    }
+}
+
+/// A rectangular box with faces parallel to the axes, which is made of six quads facing out.
+///
+/// This is not called `Box` to avoid confusion with the standard library.
+pub struct Cuboid {
+    sides: [Quad; 6],
+    bounding_box: Aabb,
+}
+impl Cuboid {
+    /// The box with opposite corners at `a` and `b`.
+    pub fn new(a: Point, b: Point, material: Arc<dyn Material>) -> Self {
+        let bounding_box = Aabb::from_corners(a, b);
+        let (min, max) = (bounding_box.min, bounding_box.max);
+        let size = max - min;
+        let (dx, dy, dz) = (
+            Vector::new(size.x, 0., 0.),
+            Vector::new(0., size.y, 0.),
+            Vector::new(0., 0., size.z),
+        );
+
+        let side = |corner, u, v| Quad::new(corner, u, v, material.clone());
+        Self {
+            sides: [
+                side(Point::new(min.x, min.y, max.z), dx, dy),
+                side(Point::new(max.x, min.y, max.z), -dz, dy),
+                side(Point::new(max.x, min.y, min.z), -dx, dy),
+                side(Point::new(min.x, min.y, min.z), dz, dy),
+                side(Point::new(min.x, max.y, max.z), dx, -dz),
+                side(Point::new(min.x, min.y, min.z), dx, dz),
+            ],
+            bounding_box: bounding_box.padded(),
+        }
+    }
+}
+impl Hittable for Cuboid {
+    fn hit(&self, ray: &Ray, t_range: &RangeInclusive<f64>) -> Option<HitRecord<'_>> {
+        if !self.bounding_box.hit(ray, t_range) {
+            return None;
+        }
+
+        closest_hit(self.sides.iter(), ray, t_range)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bounding_box
+    }
+
+    fn collect_lights<'a>(&'a self, lights: &mut Vec<Light<'a>>) {
+        self.sides
+            .iter()
+            .for_each(|side| side.collect_lights(lights))
+    }
+}
+
+/// An object moved, rotated, scaled or otherwise transformed by an affine transformation.
+///
+/// Rays are transformed into the space of the object, and hits back out of it.
+/// To place the same object many times, share it by wrapping it in an [`Arc`].
+pub struct Transformed<H> {
+    object: H,
+    transform: Matrix,
+    inverse: Matrix,
+    bounding_box: Aabb,
+}
+impl<H: Hittable> Transformed<H> {
+    /// The object transformed by the matrix, or `None` if the transformation cannot be undone,
+    /// such as when scaling by zero.
+    pub fn new(object: H, transform: Matrix) -> Option<Self> {
+        let inverse = transform.invert()?;
+        Some(Self::with_inverse(object, transform, inverse))
+    }
+
+    /// The object transformed by the matrix, with its `inverse` already known.
+    fn with_inverse(object: H, transform: Matrix, inverse: Matrix) -> Self {
+        // Bound the transformed corners of the bounding box of the object
+        let Aabb { min, max } = object.bounding_box();
+        let bounding_box = iproduct!([min.x, max.x], [min.y, max.y], [min.z, max.z])
+            .map(|(x, y, z)| transform.transform_point(Point::new(x, y, z)))
+            .fold(Aabb::empty(), |bb, p| bb.union(&Aabb::new(p, p)));
+
+        Self {
+            object,
+            transform,
+            inverse,
+            bounding_box,
+        }
+    }
+
+    /// The ray in the space of the object, with the same parameters for the same points.
+    fn object_ray(&self, ray: &Ray) -> Ray {
+        Ray::new(
+            self.inverse.transform_point(ray.origin),
+            self.inverse.transform_vector(ray.direction),
+            ray.time,
+        )
+    }
+
+    /// A hit in the space of the object moved out of it.
+    fn transform_hit<'a>(&self, mut hr: HitRecord<'a>) -> HitRecord<'a> {
+        // Normals are transformed by the inverse transpose to stay perpendicular to the surface,
+        // which also keeps them facing against the ray
+        hr.point = self.transform.transform_point(hr.point);
+        hr.normal = self
+            .inverse
+            .transpose()
+            .transform_vector(hr.normal)
+            .normalize();
+        hr
+    }
+}
+impl<H: Hittable> Hittable for Transformed<H> {
+    fn hit(&self, ray: &Ray, t_range: &RangeInclusive<f64>) -> Option<HitRecord<'_>> {
+        self.object
+            .hit(&self.object_ray(ray), t_range)
+            .map(|hr| self.transform_hit(hr))
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bounding_box
+    }
+
+    /// Solid angles are stretched unevenly by the transformation, which changes the density.
+    fn pdf_value(&self, origin: &Point, direction: &Vector) -> f64 {
+        let object_ray = self.object_ray(&Ray::new(*origin, direction.normalize(), 0.));
+        let pdf = self
+            .object
+            .pdf_value(&object_ray.origin, &object_ray.direction);
+
+        pdf * self.inverse.determinant().abs() / object_ray.direction.magnitude().powi(3)
+    }
+
+    fn random_direction(&self, rng: &mut UsedRng, origin: &Point) -> Option<Vector> {
+        self.object
+            .random_direction(rng, &self.inverse.transform_point(*origin))
+            .map(|direction| self.transform.transform_vector(direction))
+    }
+
+    /// Each light within the object is sampled in the space of the object through its own
+    /// transformed view of it.
+    fn collect_lights<'a>(&'a self, lights: &mut Vec<Light<'a>>) {
+        let mut object_lights = Vec::new();
+        self.object.collect_lights(&mut object_lights);
+        lights.extend(object_lights.into_iter().map(|light| Light {
+            sampler: Box::new(Transformed::with_inverse(
+                light.sampler,
+                self.transform,
+                self.inverse,
+            )),
+            object: light.object,
+        }));
+    }
+
+    fn sample_medium(
+        &self,
+        rng: &mut UsedRng,
+        ray: &Ray,
+        t_range: &RangeInclusive<f64>,
+    ) -> Option<HitRecord<'_>> {
+        self.object
+            .sample_medium(rng, &self.object_ray(ray), t_range)
+            .map(|hr| self.transform_hit(hr))
+    }
+
+    fn collect_media<'a>(&'a self, media: &mut Vec<Box<dyn Hittable + 'a>>) {
+        let mut object_media = Vec::new();
+        self.object.collect_media(&mut object_media);
+        media.extend(object_media.into_iter().map(|medium| {
+            Box::new(Transformed::with_inverse(
+                medium,
+                self.transform,
+                self.inverse,
+            )) as Box<dyn Hittable + 'a>
+        }));
+    }
+}
+
+/// A volume of constant density such as fog or smoke, filling a closed boundary object, in which
+/// rays scatter off of particles made of the `phase` material at random distances.
+pub struct ConstantMedium<H> {
+    boundary: H,
+    density: f64,
+    phase: Arc<dyn Material>,
+}
+impl<H: Hittable> ConstantMedium<H> {
+    /// The volume within the `boundary`, which should be convex so that rays cross it at most once,
+    /// with higher densities scattering rays after shorter distances.
+    pub fn new(boundary: H, density: f64, phase: Arc<dyn Material>) -> Self {
+        Self {
+            boundary,
+            density,
+            phase,
+        }
+    }
+}
+impl<H: Hittable> Hittable for ConstantMedium<H> {
+    /// Rays pass through the volume, with the particles that they hit being sampled separately.
+    fn hit(&self, _ray: &Ray, _t_range: &RangeInclusive<f64>) -> Option<HitRecord<'_>> {
+        None
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.boundary.bounding_box()
+    }
+
+    fn sample_medium(
+        &self,
+        rng: &mut UsedRng,
+        ray: &Ray,
+        t_range: &RangeInclusive<f64>,
+    ) -> Option<HitRecord<'_>> {
+        // Find where the whole line enters and leaves the boundary, and then the part within range
+        let entry = self
+            .boundary
+            .hit(ray, &(f64::NEG_INFINITY..=f64::INFINITY))?
+            .t;
+        let exit = self
+            .boundary
+            .hit(ray, &(entry + MIN_HIT_DISTANCE..=f64::INFINITY))?
+            .t;
+        let entry = entry.max(*t_range.start());
+        let exit = exit.min(*t_range.end());
+        if entry >= exit {
+            return None;
+        }
+
+        // The distance to the next particle is exponentially distributed
+        let ray_length = ray.direction.magnitude();
+        let distance = -(1. - rng.gen::<f64>()).ln() / self.density;
+        if distance > (exit - entry) * ray_length {
+            return None;
+        }
+
+        // The surface normal and coordinates are meaningless inside a volume
+        Some(HitRecord::new(
+            self,
+            self.phase.as_ref(),
+            ray,
+            entry + distance / ray_length,
+            Vector::unit_x(),
+            (0., 0.),
+        ))
+    }
+
+    fn collect_media<'a>(&'a self, media: &mut Vec<Box<dyn Hittable + 'a>>) {
+        media.push(Box::new(self));
+    }
+}
@@ -1,10 +1,14 @@
+//! Images and their colors.
+
+use crate::tonemap::ToneMapping;
 use cgmath::Vector3;
 use derive_new::new;
-use easy_cast::ConvFloat;
+use easy_cast::{Conv, ConvFloat};
 use num::{rational::Ratio, ToPrimitive};
 
 const MAX_COLOR_CHANNEL: u8 = 255;
 
+/// The dimensions of an image.
 #[derive(Debug, Clone, Copy, new)]
 pub struct Size<T> {
     pub width: T,
@@ -14,54 +18,107 @@ impl<T> Size<T>
 where
     T: std::ops::Mul<T, Output = T> + num::Integer + ToPrimitive + num::bigint::ToBigInt + Copy,
 {
+    /// The number of pixels.
     pub fn len(&self) -> T {
         self.width * self.height
     }
 
+    /// The ratio of width to height.
     pub fn aspect_ratio(&self) -> f64 {
         Ratio::new(self.width, self.height).to_f64().unwrap()
     }
 }
 
 type Channel = f64;
+/// A linear RGB color, with channels that may exceed one.
 pub type Color = Vector3<Channel>;
 
-struct ColorDisplay(Color);
-impl ColorDisplay {
-    fn apply_gamma_correction(x: f64) -> f64 {
-        x.sqrt()
+struct ColorDisplay<'a>(Color, &'a ToneMapping);
+impl ColorDisplay<'_> {
+    /// Tone maps a linear color and scales each channel to an integer in `[0, max]`.
+    fn quantize(color: Color, tone_mapping: &ToneMapping, max: u16) -> Vector3<u16> {
+        tone_mapping
+            .map(color)
+            .map(|x| u16::conv_nearest(Channel::from(max) * x))
     }
 }
-impl std::fmt::Display for ColorDisplay {
+impl std::fmt::Display for ColorDisplay<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let color = self.0.map(|x| {
-            u8::conv_nearest(Channel::from(MAX_COLOR_CHANNEL) * Self::apply_gamma_correction(x))
-        });
+        let color = Self::quantize(self.0, self.1, MAX_COLOR_CHANNEL.into());
 
         write!(f, "{} {} {}", color.x, color.y, color.z,)
     }
 }
 
-#[derive(new)]
-pub struct Image {
-    size: Size<usize>,
-    // Pixel colors in row major order
-    pixel_data: Box<[Color]>,
+/// Displays an image as PPM text.
+pub struct ImageDisplay<'a> {
+    image: &'a Image,
+    tone_mapping: &'a ToneMapping,
 }
-impl std::fmt::Display for Image {
+impl std::fmt::Display for ImageDisplay<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         // Print header: P3 format, image size, and max color value
         writeln!(
             f,
             "P3\n{} {}\n{MAX_COLOR_CHANNEL}",
-            self.size.width, self.size.height
+            self.image.size.width, self.image.size.height
         )?;
 
         // Print pixel colors
-        for color in self.pixel_data.iter() {
-            writeln!(f, "{}", ColorDisplay(*color))?;
+        for color in self.image.pixel_data.iter() {
+            writeln!(f, "{}", ColorDisplay(*color, self.tone_mapping))?;
         }
 
         Ok(())
     }
 }
+
+/// A rendered image of linear colors.
+#[derive(new, Debug)]
+pub struct Image {
+    size: Size<usize>,
+    // Pixel colors in row major order
+    pixel_data: Box<[Color]>,
+}
+impl Image {
+    /// The dimensions in pixels.
+    pub fn size(&self) -> Size<usize> {
+        self.size
+    }
+
+    /// The raw linear pixel colors in row major order, starting from the top left.
+    pub fn pixel_data(&self) -> &[Color] {
+        &self.pixel_data
+    }
+
+    /// The tone mapped red, green and blue channels of every pixel in row major order,
+    /// each scaled to an integer in `[0, max]`.
+    pub fn quantized_channels<'a>(
+        &'a self,
+        tone_mapping: &'a ToneMapping,
+        max: u16,
+    ) -> impl Iterator<Item = u16> + 'a {
+        self.pixel_data.iter().flat_map(move |color| {
+            let color = ColorDisplay::quantize(*color, tone_mapping, max);
+            [color.x, color.y, color.z]
+        })
+    }
+
+    /// Like [`Image::quantized_channels`], but with each channel packed into either one byte if `max`
+    /// fits, or two big-endian bytes otherwise, as is used by many binary image formats.
+    pub fn quantized_bytes(&self, tone_mapping: &ToneMapping, max: u16) -> Vec<u8> {
+        let channels = self.quantized_channels(tone_mapping, max);
+        match u8::try_from(max) {
+            Ok(_) => channels.map(u8::conv).collect(),
+            Err(_) => channels.flat_map(|x| x.to_be_bytes()).collect(),
+        }
+    }
+
+    /// Displays the image as PPM text with 8-bit channels.
+    pub fn display<'a>(&'a self, tone_mapping: &'a ToneMapping) -> ImageDisplay<'a> {
+        ImageDisplay {
+            image: self,
+            tone_mapping,
+        }
+    }
+}
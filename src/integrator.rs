@@ -0,0 +1,281 @@
+//! Algorithms for computing the light arriving along camera rays.
+
+use crate::{
+    background::Background,
+    hittable::{HitRecord, Hittable, Light, MIN_HIT_DISTANCE},
+    image::Color,
+    material::Scatter,
+    math::{HittablePdf, Pdf, Ray, Vector},
+    UsedRng,
+};
+use cgmath::{ElementWise, Zero};
+use easy_cast::Conv;
+use rand::Rng;
+use std::collections::HashMap;
+
+/// Everything that light can come from or be scattered by.
+pub struct World<'a> {
+    pub objects: &'a dyn Hittable,
+    pub background: &'a dyn Background,
+    /// The objects that emit light and can be sampled directly.
+    pub lights: Vec<Light<'a>>,
+    /// The volumes among the objects, in which rays are randomly scattered or absorbed.
+    pub media: Vec<Box<dyn Hittable + 'a>>,
+    /// The indices of the lights of each primitive object by its address, of which there are
+    /// several if the object is placed in the scene more than once.
+    light_indices: HashMap<usize, Vec<usize>>,
+}
+impl<'a> World<'a> {
+    /// The world of the `objects` and `background`, gathering the lights and media among the
+    /// objects.
+    pub fn new(objects: &'a dyn Hittable, background: &'a dyn Background) -> Self {
+        let mut lights = Vec::new();
+        objects.collect_lights(&mut lights);
+        let mut light_indices = HashMap::<_, Vec<_>>::new();
+        for (index, light) in lights.iter().enumerate() {
+            light_indices
+                .entry(address(light.object))
+                .or_default()
+                .push(index);
+        }
+        let mut media = Vec::new();
+        objects.collect_media(&mut media);
+
+        Self {
+            objects,
+            background,
+            lights,
+            media,
+            light_indices,
+        }
+    }
+
+    /// The closest hit of a ray in the world, which may be with a volume where the ray is randomly
+    /// scattered or absorbed before reaching any surface.
+    fn hit(&self, rng: &mut UsedRng, ray: &Ray) -> Option<HitRecord<'_>> {
+        // Each volume only needs sampling up to the closest hit so far
+        let mut closest = self.objects.hit(ray, &(MIN_HIT_DISTANCE..=f64::INFINITY));
+        for medium in &self.media {
+            let t_max = closest.as_ref().map_or(f64::INFINITY, |hr| hr.t);
+            if let Some(hr) = medium.sample_medium(rng, ray, &(MIN_HIT_DISTANCE..=t_max)) {
+                closest = Some(hr);
+            }
+        }
+        closest
+    }
+
+    /// The number of lights to choose from when sampling them, which includes the background if
+    /// it can be sampled.
+    fn light_count(&self) -> usize {
+        self.lights.len() + usize::from(self.background.is_sampled())
+    }
+
+    /// The probability density with respect to solid angle of choosing the direction of the `ray`
+    /// when sampling the `light` after choosing one at random.
+    fn light_pdf(&self, light: &Light, ray: &Ray) -> f64 {
+        HittablePdf::new(light.sampler.as_ref(), ray.origin).value(&ray.direction)
+            / f64::conv(self.light_count())
+    }
+
+    /// The probability density with respect to solid angle of choosing the direction of the `ray`
+    /// when sampling whichever light it hit first, as given by the hit record.
+    fn hit_light_pdf(&self, ray: &Ray, hr: &HitRecord) -> f64 {
+        let Some(indices) = self.light_indices.get(&address(hr.object)) else {
+            return 0.;
+        };
+        indices
+            .iter()
+            .map(|index| &self.lights[*index])
+            .filter(|light| light.hits_at(ray, hr.t))
+            .map(|light| self.light_pdf(light, ray))
+            .sum()
+    }
+
+    /// The probability density with respect to solid angle of choosing the direction when
+    /// sampling the background after choosing it at random among the lights.
+    fn background_pdf(&self, direction: &Vector) -> f64 {
+        if self.background.is_sampled() {
+            self.background.pdf_value(direction) / f64::conv(self.light_count())
+        } else {
+            0.
+        }
+    }
+}
+
+/// The address of an object, which identifies it.
+fn address(object: &dyn Hittable) -> usize {
+    std::ptr::from_ref(object).cast::<()>().addr()
+}
+
+/// An algorithm for estimating the light arriving at the camera along a ray.
+///
+/// Any [`Debug`](std::fmt::Debug) output should include all settings that affect the result,
+/// since it is used to tell whether a [checkpoint](crate::checkpoint) can be resumed.
+pub trait Integrator: std::fmt::Debug + Send + Sync {
+    /// A random estimate of the light arriving back along the ray from the world, following the
+    /// ray for at most `max_depth` bounces.
+    fn radiance(&self, rng: &mut UsedRng, ray: &Ray, world: &World, max_depth: usize) -> Color;
+}
+
+/// The weight of a sample taken with the density `pdf` when another strategy could have taken it
+/// with the density `other_pdf`, which is nearly optimal for multiple importance sampling.
+fn power_heuristic(pdf: f64, other_pdf: f64) -> f64 {
+    pdf.powi(2) / (pdf.powi(2) + other_pdf.powi(2))
+}
+
+/// Unidirectional path tracing, where each ray is followed as it randomly scatters off of
+/// materials until it is absorbed or escapes the scene.
+#[derive(Debug, Clone)]
+pub struct PathTracer {
+    /// The number of bounces after which paths that carry little light are randomly terminated
+    /// by Russian roulette, with the surviving paths being weighted to compensate.
+    pub roulette_depth: usize,
+    /// Whether to also sample the lights directly at each non-specular bounce, combining this with
+    /// the scattered rays by multiple importance sampling, which greatly reduces the noise from
+    /// small lights.
+    pub light_sampling: bool,
+}
+impl PathTracer {
+    /// The light arriving directly from a randomly chosen light or the background that is
+    /// scattered along the ray by a non-specular surface, weighted for combining with hitting
+    /// lights by scattering from the `scatter_pdf`.
+    fn sample_light(
+        &self,
+        rng: &mut UsedRng,
+        ray: &Ray,
+        hr: &HitRecord,
+        scatter_pdf: &dyn Pdf,
+        world: &World,
+    ) -> Color {
+        // The background is chosen if the choice is past the end of the lights
+        let light = world.lights.get(rng.gen_range(0..world.light_count()));
+        let direction = match light {
+            Some(light) => HittablePdf::new(light.sampler.as_ref(), hr.point).generate(rng),
+            None => world.background.random_direction(rng),
+        };
+        let Some(direction) = direction else {
+            return Color::zero();
+        };
+        let shadow_ray = Ray::new(hr.point, direction, ray.time);
+        let light_pdf = match light {
+            Some(light) => world.light_pdf(light, &shadow_ray),
+            None => world.background_pdf(&direction),
+        };
+        let reflectance = hr.material.eval(ray, hr, &direction);
+        if light_pdf <= 0. || reflectance.is_zero() {
+            return Color::zero();
+        }
+
+        // The light only arrives if nothing else is in the way
+        let emitted = match (world.hit(rng, &shadow_ray), light) {
+            (Some(light_hr), Some(light))
+                if std::ptr::addr_eq(light_hr.object, light.object)
+                    && light.hits_at(&shadow_ray, light_hr.t) =>
+            {
+                light_hr.material.emitted(&light_hr)
+            }
+            (None, None) => world.background.color(&direction),
+            _ => return Color::zero(),
+        };
+        let weight = power_heuristic(light_pdf, scatter_pdf.value(&direction));
+        reflectance.mul_element_wise(emitted) * weight / light_pdf
+    }
+}
+impl Default for PathTracer {
+    fn default() -> Self {
+        Self {
+            roulette_depth: 3,
+            light_sampling: true,
+        }
+    }
+}
+impl Integrator for PathTracer {
+    fn radiance(&self, rng: &mut UsedRng, ray: &Ray, world: &World, max_depth: usize) -> Color {
+        let light_sampling = self.light_sampling && world.light_count() > 0;
+        let mut radiance = Color::zero();
+        // The fraction of the light from the current ray that makes it back to the camera
+        let mut throughput = Color::new(1., 1., 1.);
+        let mut ray = *ray;
+        // The density with which the last bounce scattered the ray, unless it was specular
+        let mut scatter_pdf = None;
+        // Light that is hit must be weighted against sampling it directly from the last bounce
+        // if that was done, given the density of sampling it
+        let mis_weight = |scatter_pdf: Option<f64>, light_pdf: f64| match scatter_pdf {
+            Some(pdf) if light_sampling => power_heuristic(pdf, light_pdf),
+            _ => 1.,
+        };
+
+        for depth in 0..max_depth {
+            let Some(hr) = world.hit(rng, &ray) else {
+                let weight = mis_weight(scatter_pdf, world.background_pdf(&ray.direction));
+                radiance +=
+                    throughput.mul_element_wise(world.background.color(&ray.direction)) * weight;
+                break;
+            };
+
+            // Add any light the material itself emits
+            let weight = mis_weight(scatter_pdf, world.hit_light_pdf(&ray, &hr));
+            radiance += throughput.mul_element_wise(hr.material.emitted(&hr)) * weight;
+
+            // Scatter based on the material
+            match hr.material.scatter(rng, &ray, &hr) {
+                Scatter::Absorbed => break,
+                Scatter::Specular {
+                    attenuation,
+                    ray: scattered,
+                } => {
+                    throughput.mul_assign_element_wise(attenuation);
+                    scatter_pdf = None;
+                    ray = scattered;
+                }
+                Scatter::Pdf(pdf) => {
+                    if light_sampling {
+                        radiance += throughput.mul_element_wise(self.sample_light(
+                            rng,
+                            &ray,
+                            &hr,
+                            pdf.as_ref(),
+                            world,
+                        ));
+                    }
+
+                    // Importance sample the direction, weighting by the density
+                    let Some(direction) = pdf.generate(rng) else {
+                        break;
+                    };
+                    let density = pdf.value(&direction);
+                    if density <= 0. {
+                        break;
+                    }
+                    throughput
+                        .mul_assign_element_wise(hr.material.eval(&ray, &hr, &direction) / density);
+                    scatter_pdf = Some(density);
+                    ray = Ray::new(hr.point, direction, ray.time);
+                }
+            }
+
+            // Randomly end paths that can only contribute a little, boosting those that survive
+            if depth + 1 >= self.roulette_depth {
+                let survival = throughput.x.max(throughput.y).max(throughput.z).min(1.);
+                if rng.gen::<f64>() >= survival {
+                    break;
+                }
+                throughput /= survival;
+            }
+        }
+
+        radiance
+    }
+}
+
+/// Shows the surface normal of the first hit as a color, which is useful for checking geometry.
+#[derive(Debug, Clone)]
+pub struct Normals;
+impl Integrator for Normals {
+    fn radiance(&self, rng: &mut UsedRng, ray: &Ray, world: &World, _max_depth: usize) -> Color {
+        match world.hit(rng, ray) {
+            Some(hr) => 0.5 * (hr.normal + Color::new(1., 1., 1.)),
+            None => Color::zero(),
+        }
+    }
+}
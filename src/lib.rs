@@ -0,0 +1,55 @@
+//! A basic ray tracer, following the [Ray Tracing in One Weekend](https://raytracing.github.io/)
+//! series of books.
+//!
+//! A [`Scene`](scene::Scene) is either built in code from [`hittable`] objects and their
+//! [`material`]s, or loaded from a scene file. It is then rendered by a [`Camera`](camera::Camera),
+//! configured with [`CameraSettings`](camera::CameraSettings), into a linear
+//! [`Image`](image::Image), which can finally be written to a file with [`writer::write_image`].
+//!
+//! ```no_run
+//! use num::rational::Ratio;
+//! use rand::SeedableRng;
+//! use ray_tracing::{
+//!     bvh::BvhNode,
+//!     camera::Camera,
+//!     scene::Scene,
+//!     writer::{write_image, EncodeSettings, ImageFormat},
+//!     UsedRng,
+//! };
+//! use std::path::Path;
+//!
+//! let mut rng = UsedRng::seed_from_u64(0);
+//! let scene = Scene::random_spheres(&mut rng);
+//! let camera = Camera::new(400, Ratio::new(16, 9), &scene.camera.samples_per_pixel(10));
+//! let image = camera.render(&BvhNode::new(scene.objects), scene.background.as_ref()).image;
+//!
+//! let path = Path::new("image.png");
+//! write_image(path, ImageFormat::from_path(path)?, &image, &EncodeSettings::default())?;
+//! # Ok::<(), ray_tracing::writer::WriteError>(())
+//! ```
+
+pub mod background;
+pub mod bvh;
+pub mod camera;
+pub mod checkpoint;
+pub mod hittable;
+pub mod image;
+pub mod integrator;
+pub mod material;
+pub mod math;
+pub mod mesh;
+pub mod reader;
+pub mod scene;
+pub mod texture;
+pub mod tile;
+pub mod tonemap;
+pub mod volume;
+pub mod writer;
+
+/// The random number generator used throughout.
+///
+/// This needs to be a particular type and not parametrized using the [`Rng`](rand::Rng) trait because we need trait objects.
+///
+/// This is seedable and has independent streams, so that renders are reproducible.
+/// Stream zero is used for generating scenes, and the camera uses the others for each pixel.
+pub type UsedRng = rand_chacha::ChaCha8Rng;
@@ -1,111 +1,372 @@
-#![feature(cmp_minmax)]
-
-use crate::{
-    hittable::{Hittable, HittableList, Sphere},
-    image::Color,
-    material::{Dielectric, Lambertian, Metal},
-    math::VectorExt,
-};
-use camera::Camera;
-use cgmath::{ElementWise, InnerSpace};
-use clap::Parser;
-use itertools::iproduct;
-use math::Point;
+use clap::{builder::RangedU64ValueParser, Parser};
 use num::rational::Ratio;
-use rand::{thread_rng, Rng};
-
-mod camera;
-mod hittable;
-mod image;
-mod material;
-mod math;
-
-/// This needs to be a particular type and not parametrized using the [`Rng`](rand::Rng) trait because we need trait objects.
-type UsedRng = rand::rngs::ThreadRng;
+use rand::SeedableRng;
+use ray_tracing::{
+    bvh::BvhNode,
+    camera::{Camera, CameraSettings},
+    checkpoint::{hash_bytes, hash_scene, save_checkpoint, Checkpoint},
+    image::Image,
+    integrator::{Normals, PathTracer},
+    math::{Point, Vector},
+    scene::{Scene, SceneError},
+    tile::TileOrder,
+    tonemap::{ToneMapOperator, ToneMapping},
+    writer::{write_image, BitDepth, EncodeSettings, ImageFormat},
+    UsedRng,
+};
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 /// A basic ray tracer, following the 'Ray Tracing in One Weekend' series of books.
-/// Prints PPM image text.
+/// Writes the image to a file, or prints PPM image text if no output file is given.
 #[derive(Parser, Debug)]
 #[command(author, about)]
 struct Args {
     /// Render image width, with the height being determined by a 16:9 aspect ratio.
     #[arg(short = 'w', long, default_value_t = 400)]
     image_width: usize,
+    /// Scene description file to render, see the README for the format.
+    /// Renders the final scene of the first book with random spheres if omitted.
+    #[arg(short, long)]
+    scene: Option<PathBuf>,
+    /// Image file to write, with the format determined by the extension (png, ppm, hdr, pfm or exr).
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+    /// Bits per color channel for the output image file.
+    #[arg(short, long, value_enum, default_value_t)]
+    bit_depth: BitDepth,
+    /// Exposure adjustment in stops applied before tone mapping, so each increment doubles the brightness.
+    #[arg(short, long, default_value_t = 0., allow_hyphen_values = true)]
+    exposure: f64,
+    /// Tone mapping operator used to bring bright colors into the displayable range.
+    /// Does not affect the high dynamic range formats.
+    #[arg(short, long, value_enum, default_value_t)]
+    tone_map: ToneMapOperator,
+    /// Luminance that is mapped to white by the extended Reinhard tone mapping operator.
+    #[arg(long, default_value_t = ToneMapping::default().white_point)]
+    white_point: f64,
+    /// Seed for all random numbers, so that the same seed, scene and settings always render the same image.
+    /// A random seed is used if omitted.
+    #[arg(long)]
+    seed: Option<u64>,
+    /// Side length in pixels of the square tiles that are rendered in parallel.
+    #[arg(long, default_value_t = 32, value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+    tile_size: usize,
+    /// Order in which the tiles are rendered.
+    #[arg(long, value_enum, default_value_t)]
+    tile_order: TileOrder,
+    /// Algorithm used to compute the light arriving along each ray.
+    #[arg(long, value_enum, default_value_t)]
+    integrator: IntegratorChoice,
+    /// Number of bounces after which path tracing randomly terminates paths that carry little light.
+    #[arg(long, default_value_t = PathTracer::default().roulette_depth)]
+    roulette_depth: usize,
+    /// Disables sampling the lights directly when path tracing, so that lights are only found by
+    /// scattered rays hitting them.
+    #[arg(long)]
+    no_light_sampling: bool,
+    /// Renders progressively in passes of this many samples per pixel, writing the output image
+    /// after each pass so that long renders can be inspected and stopped early.
+    #[arg(long, value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+    pass_samples: Option<usize>,
+    /// When rendering progressively, the minimum number of seconds between writing the output image.
+    #[arg(long, default_value = "0", value_parser = parse_seconds, requires = "pass_samples")]
+    write_interval: Duration,
+    /// Checkpoint file to save the render to after each pass when rendering progressively, at most
+    /// every `--write-interval` seconds.
+    #[arg(long, requires = "pass_samples")]
+    checkpoint: Option<PathBuf>,
+    /// Continues the render saved in the `--checkpoint` file, which must be of the same scene with
+    /// the same camera settings.
+    #[arg(long, requires = "checkpoint")]
+    resume: bool,
+    /// Image file to write showing the number of samples taken for each pixel, from black for none
+    /// through red and yellow to white for the maximum, which is useful with adaptive sampling.
+    #[arg(long)]
+    sample_heatmap: Option<PathBuf>,
+    #[command(flatten)]
+    camera: CameraArgs,
+}
+
+/// The available integrators.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default)]
+enum IntegratorChoice {
+    /// Path tracing with Russian roulette.
+    #[default]
+    Path,
+    /// Surface normals as colors, for checking geometry.
+    Normals,
+}
+
+/// Camera settings that override those of the scene when given.
+#[derive(clap::Args, Debug)]
+#[command(next_help_heading = "Camera")]
+struct CameraArgs {
+    /// The location of the focal point of the camera, as `x,y,z`.
+    #[arg(long, value_parser = parse_point, allow_hyphen_values = true)]
+    look_from: Option<Point>,
+    /// Point the center of the camera is aimed towards, as `x,y,z`.
+    #[arg(long, value_parser = parse_point, allow_hyphen_values = true)]
+    look_at: Option<Point>,
+    /// Camera-relative up direction, as `x,y,z`.
+    #[arg(long, value_parser = parse_vector, allow_hyphen_values = true)]
+    up: Option<Vector>,
+    /// Vertical camera field of view in degrees.
+    #[arg(long)]
+    vfov: Option<f64>,
+    /// Variation angle of rays through each pixel in degrees, with zero disabling defocus blur.
+    #[arg(long)]
+    defocus_angle: Option<f64>,
+    /// Distance from the camera look from point to the plane of perfect focus.
+    #[arg(long)]
+    focus_distance: Option<f64>,
+    /// The time at which the camera shutter opens, with moving objects being blurred while it is open.
+    #[arg(long, allow_hyphen_values = true)]
+    shutter_open: Option<f64>,
+    /// The time at which the camera shutter closes.
+    #[arg(long, allow_hyphen_values = true)]
+    shutter_close: Option<f64>,
+    /// Number of random samples averaged to render a single pixel.
+    #[arg(short = 'n', long, value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+    samples: Option<usize>,
+    /// Enables adaptive sampling, which stops sampling a pixel once the standard error of its
+    /// luminance relative to the luminance is below this, with `--samples` then being the maximum.
+    #[arg(long, value_parser = parse_positive)]
+    noise_threshold: Option<f64>,
+    /// Number of samples taken for every pixel with adaptive sampling before checking the noise.
+    #[arg(long)]
+    min_samples: Option<usize>,
+    /// The maximum number of ray bounces before just being black.
+    #[arg(long, value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+    max_depth: Option<usize>,
+}
+impl CameraArgs {
+    /// Overrides the settings with those that were given.
+    fn apply(&self, mut settings: CameraSettings) -> CameraSettings {
+        if let Some(look_from) = self.look_from {
+            settings = settings.look_from(look_from);
+        }
+        if let Some(look_at) = self.look_at {
+            settings = settings.look_at(look_at);
+        }
+        if let Some(up) = self.up {
+            settings = settings.up_direction(up);
+        }
+        if let Some(vfov) = self.vfov {
+            settings = settings.vertical_fov(vfov);
+        }
+        if let Some(defocus_angle) = self.defocus_angle {
+            settings = settings.defocus_angle(defocus_angle);
+        }
+        if let Some(focus_distance) = self.focus_distance {
+            settings = settings.focus_distance(focus_distance);
+        }
+        if let Some(shutter_open) = self.shutter_open {
+            settings = settings.shutter_open(shutter_open);
+        }
+        if let Some(shutter_close) = self.shutter_close {
+            settings = settings.shutter_close(shutter_close);
+        }
+        if let Some(samples) = self.samples {
+            settings = settings.samples_per_pixel(samples);
+        }
+        if let Some(noise_threshold) = self.noise_threshold {
+            settings = settings.noise_threshold(noise_threshold);
+        }
+        if let Some(min_samples) = self.min_samples {
+            settings = settings.min_samples_per_pixel(min_samples);
+        }
+        if let Some(max_depth) = self.max_depth {
+            settings = settings.max_depth(max_depth);
+        }
+
+        settings
+    }
+}
+
+fn parse_vector(s: &str) -> Result<Vector, String> {
+    let components = s
+        .split(',')
+        .map(|c| c.trim().parse::<f64>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+
+    match components[..] {
+        [x, y, z] => Ok(Vector::new(x, y, z)),
+        _ => Err("expected three comma-separated numbers like `1,0.5,2`".into()),
+    }
+}
+
+fn parse_point(s: &str) -> Result<Point, String> {
+    parse_vector(s).map(|v| Point::new(v.x, v.y, v.z))
+}
+
+fn parse_positive(s: &str) -> Result<f64, String> {
+    let x = s.trim().parse::<f64>().map_err(|e| e.to_string())?;
+    if x.is_finite() && x > 0. {
+        Ok(x)
+    } else {
+        Err("expected a finite number greater than zero".into())
+    }
+}
+
+fn parse_seconds(s: &str) -> Result<Duration, String> {
+    let seconds = s.trim().parse::<f64>().map_err(|e| e.to_string())?;
+    Duration::try_from_secs_f64(seconds).map_err(|e| e.to_string())
 }
 
 fn main() {
     // Parse arguments
     let args = Args::parse();
 
+    // Load any checkpoint first, since its seed is needed to set up the scene and camera again
+    let checkpoint = args
+        .checkpoint
+        .as_ref()
+        .filter(|_| args.resume)
+        .map(|path| match Checkpoint::load(path) {
+            Ok(checkpoint) => checkpoint,
+            Err(e) => {
+                eprintln!("Error loading checkpoint `{}`: {e}", path.display());
+                std::process::exit(1);
+            }
+        });
+
+    // Load or generate the scene
+    let seed = args
+        .seed
+        .or(checkpoint.as_ref().map(Checkpoint::seed))
+        .unwrap_or_else(rand::random);
+    let mut rng = UsedRng::seed_from_u64(seed);
+    let (scene, scene_hash) = match &args.scene {
+        Some(path) => match std::fs::read_to_string(path)
+            .map_err(SceneError::from)
+            .and_then(|source| {
+                let directory = path.parent().unwrap_or(Path::new(""));
+                let scene = Scene::parse(&source, directory, &mut rng)?;
+                let scene_hash = hash_scene(&source, &scene.files)?;
+                Ok((scene, scene_hash))
+            }) {
+            Ok(scene) => scene,
+            Err(e) => {
+                eprintln!("Error loading scene `{}`: {e}", path.display());
+                std::process::exit(1);
+            }
+        },
+        // The scene is entirely determined by the seed
+        None => (Scene::random_spheres(&mut rng), hash_bytes(&[])),
+    };
+
+    // Determine the output formats before spending time rendering
+    let output = args.output.as_ref().map(|path| (path, image_format(path)));
+    let sample_heatmap = args
+        .sample_heatmap
+        .as_ref()
+        .map(|path| (path, image_format(path)));
+
     // Setup camera
-    let camera = Camera::new(args.image_width, Ratio::new(16, 9));
-
-    let mut world = vec![
-        // Large ground sphere
-        Sphere::new(
-            Point::new(0., -1000., 0.),
-            1000.,
-            Box::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))),
-        ),
-    ];
-
-    // Add random little spheres
-    let mut rng = thread_rng();
-    let gap_point = Point::new(4., 0.2, 0.);
-    for (a, b) in iproduct!(-11..11, -11..11) {
-        let center = Point::new(
-            f64::from(a) + 0.9 * rng.gen::<f64>(),
-            0.2,
-            f64::from(b) + 0.9 * rng.gen::<f64>(),
-        );
+    let camera = Camera::new(
+        args.image_width,
+        Ratio::new(16, 9),
+        &args
+            .camera
+            .apply(scene.camera)
+            .integrator(match args.integrator {
+                IntegratorChoice::Path => Arc::new(PathTracer {
+                    roulette_depth: args.roulette_depth,
+                    light_sampling: !args.no_light_sampling,
+                }),
+                IntegratorChoice::Normals => Arc::new(Normals),
+            })
+            .seed(seed)
+            .tile_size(args.tile_size)
+            .tile_order(args.tile_order),
+    );
+
+    // Render image
+    let settings = EncodeSettings {
+        bit_depth: args.bit_depth,
+        tone_mapping: ToneMapping {
+            exposure: args.exposure,
+            operator: args.tone_map,
+            white_point: args.white_point,
+        },
+    };
+    let objects = BvhNode::new(scene.objects);
+    let render = match args.pass_samples {
+        Some(pass_samples) => {
+            let accumulation = match checkpoint {
+                Some(checkpoint) => match checkpoint.resume(&camera, scene_hash, pass_samples) {
+                    Ok(accumulation) => accumulation,
+                    Err(e) => {
+                        eprintln!("Error resuming from checkpoint: {e}");
+                        std::process::exit(1);
+                    }
+                },
+                None => camera.accumulation(),
+            };
 
-        if (center - gap_point).magnitude() > 0.9 {
-            world.push(Sphere::new(
-                center,
-                0.2,
-                match rng.gen::<f64>() {
-                    x if x < 0.8 => {
-                        // The squaring here ensures darker colors
-                        let color = Color::random_unit_cube(&mut rng)
-                            .mul_element_wise(Color::random_unit_cube(&mut rng));
-                        Box::new(Lambertian::new(color))
+            let mut last_write = Instant::now();
+            camera.render_progressive(
+                &objects,
+                scene.background.as_ref(),
+                accumulation,
+                pass_samples,
+                |accumulation| {
+                    if last_write.elapsed() < args.write_interval {
+                        return;
+                    }
+                    if let Some((path, format)) = output {
+                        save_image(path, format, &accumulation.render().image, &settings);
                     }
-                    x if x < 0.95 => {
-                        let color = Color::random(&mut rng, 0.5..1.);
-                        Box::new(Metal::new(color, 0.5 * rng.gen::<f64>()))
+                    if let Some(path) = &args.checkpoint {
+                        if let Err(e) =
+                            save_checkpoint(path, &camera, scene_hash, pass_samples, accumulation)
+                        {
+                            eprintln!("Error saving checkpoint `{}`: {e}", path.display());
+                            std::process::exit(1);
+                        }
                     }
-                    _ => Box::new(Dielectric::new(1.5)),
+                    last_write = Instant::now();
                 },
-            ))
+            )
         }
+        None => camera.render(&objects, scene.background.as_ref()),
+    };
+
+    // Output image
+    match output {
+        Some((path, format)) => save_image(path, format, &render.image, &settings),
+        None => println!("{}", render.image.display(&settings.tone_mapping)),
+    }
+    if let Some((path, format)) = sample_heatmap {
+        save_image(
+            path,
+            format,
+            &render.sample_heatmap(),
+            &EncodeSettings::default(),
+        );
     }
+}
 
-    // Add constant large spheres
-    world.extend([
-        // Glass
-        Sphere::new(Point::new(0., 1., 0.), 1., Box::new(Dielectric::new(1.5))),
-        // Solid
-        Sphere::new(
-            Point::new(-4., 1., 0.),
-            1.,
-            Box::new(Lambertian::new(Color::new(0.4, 0.2, 0.1))),
-        ),
-        // Metal
-        Sphere::new(
-            Point::new(4., 1., 0.),
-            1.,
-            Box::new(Metal::new(Color::new(0.7, 0.6, 0.5), 0.)),
-        ),
-    ]);
+/// Determines the format of an image file to be written, exiting if it is not supported.
+fn image_format(path: &Path) -> ImageFormat {
+    match ImageFormat::from_path(path) {
+        Ok(format) => format,
+        Err(e) => {
+            eprintln!("Error with output image `{}`: {e}", path.display());
+            std::process::exit(1);
+        }
+    }
+}
 
-    // Render image
-    println!(
-        "{}",
-        camera.render(&HittableList::new(
-            &world
-                .iter()
-                .map(|h| h as &dyn Hittable)
-                .collect::<Box<[_]>>(),
-        ))
-    );
+/// Writes an image file, exiting if this fails.
+fn save_image(path: &Path, format: ImageFormat, image: &Image, settings: &EncodeSettings) {
+    if let Err(e) = write_image(path, format, image, settings) {
+        eprintln!("Error writing image `{}`: {e}", path.display());
+        std::process::exit(1);
+    }
 }
@@ -1,65 +1,111 @@
+//! Materials that determine how light is scattered by surfaces.
+
 use crate::{
     hittable::HitRecord,
     image::Color,
-    math::{Ray, Vector, VectorExt},
+    math::{CosinePdf, Pdf, Ray, SpherePdf, Vector, VectorExt},
+    texture::{SolidColor, Texture},
     UsedRng,
 };
-use cgmath::InnerSpace;
+use cgmath::{InnerSpace, Zero};
 use derive_new::new;
 use num::clamp;
 use rand::Rng;
+use std::{f64::consts::PI, sync::Arc};
 
-pub struct Scatter {
-    pub attenuation: Color,
-    pub ray: Option<Ray>,
+/// The result of light hitting a material.
+pub enum Scatter {
+    /// All of the light is absorbed.
+    Absorbed,
+    /// The light comes from a single ray chosen by the material, such as the reflection in a
+    /// mirror, with `attenuation` being how much of each color channel of it comes back.
+    Specular { attenuation: Color, ray: Ray },
+    /// The light comes from a range of directions as given by [`Material::eval`], with the
+    /// distribution being a good one to importance sample them from.
+    Pdf(Box<dyn Pdf>),
 }
 
-pub trait Material: std::fmt::Debug {
+/// How the surface of an object interacts with light.
+pub trait Material: std::fmt::Debug + Send + Sync {
+    /// How the light arriving at the surface is scattered back along the ray, which may be random.
     fn scatter(&self, rng: &mut UsedRng, ray: &Ray, hit_record: &HitRecord) -> Scatter;
+
+    /// The fraction of light arriving from the `direction` that is scattered back along the ray,
+    /// including the cosine factor of the incoming light, per unit solid angle.
+    ///
+    /// This is only used for materials that scatter with a [`Scatter::Pdf`].
+    fn eval(&self, _ray: &Ray, _hit_record: &HitRecord, _direction: &Vector) -> Color {
+        Color::zero()
+    }
+
+    /// The light given off by the material at the hit point, which is black for most materials.
+    fn emitted(&self, _hit_record: &HitRecord) -> Color {
+        Color::zero()
+    }
+
+    /// Whether the material ever emits light, so that objects made of it should be sampled as lights.
+    fn is_emissive(&self) -> bool {
+        false
+    }
 }
 
+/// An ideal matte surface, which scatters light equally in all directions.
 #[derive(new, Debug)]
 pub struct Lambertian {
-    attenuation: Color,
+    attenuation: Arc<dyn Texture>,
+}
+impl Lambertian {
+    /// A Lambertian material with the same attenuation everywhere.
+    pub fn from_color(attenuation: Color) -> Self {
+        Self::new(Arc::new(SolidColor::new(attenuation)))
+    }
 }
 impl Material for Lambertian {
-    fn scatter(&self, rng: &mut UsedRng, _ray: &Ray, hit_record: &HitRecord) -> Scatter {
-        let mut scatter_direction = hit_record.normal + Vector::random_unit(rng);
-
-        // Catch degenerate scatter directions and just make them normal
-        if scatter_direction.near_zero() {
-            scatter_direction = hit_record.normal;
-        }
+    /// The scattered light follows the cosine law, so sampling by the cosine is ideal.
+    fn scatter(&self, _rng: &mut UsedRng, _ray: &Ray, hit_record: &HitRecord) -> Scatter {
+        Scatter::Pdf(Box::new(CosinePdf::new(hit_record.normal)))
+    }
 
-        Scatter {
-            attenuation: self.attenuation,
-            ray: Some(Ray::new(hit_record.point, scatter_direction)),
-        }
+    fn eval(&self, _ray: &Ray, hit_record: &HitRecord, direction: &Vector) -> Color {
+        let cosine = hit_record.normal.dot(direction.normalize()).max(0.);
+        self.attenuation
+            .value(hit_record.u, hit_record.v, &hit_record.point)
+            * (cosine / PI)
     }
 }
 
+/// A shiny surface that reflects light, possibly blurred by some fuzziness.
 #[derive(new, Debug)]
 pub struct Metal {
     /// The attenuation in the range [0, 1] for each color channel of the color that comes back from the scattered ray.
-    attenuation: Color,
+    attenuation: Arc<dyn Texture>,
     /// Radius of the random deflection sphere added to the end of the reflected ray.
     ///
     /// Zero is is perfect reflection with no fuzziness.
     fuzz_factor: f64,
 }
+impl Metal {
+    /// A metal with the same attenuation everywhere.
+    pub fn from_color(attenuation: Color, fuzz_factor: f64) -> Self {
+        Self::new(Arc::new(SolidColor::new(attenuation)), fuzz_factor)
+    }
+}
 impl Material for Metal {
     fn scatter(&self, rng: &mut UsedRng, ray: &Ray, hit_record: &HitRecord) -> Scatter {
         let fuzz = clamp(self.fuzz_factor, 0., 1.);
         let reflected = ray.direction.reflect(hit_record.normal).normalize()
             + fuzz * Vector::random_within_unit_sphere(rng);
 
-        Scatter {
-            attenuation: self.attenuation,
-            ray: Some(Ray::new(hit_record.point, reflected)),
+        Scatter::Specular {
+            attenuation: self
+                .attenuation
+                .value(hit_record.u, hit_record.v, &hit_record.point),
+            ray: Ray::new(hit_record.point, reflected, ray.time),
         }
     }
 }
 
+/// A clear material such as glass or water, which both reflects and refracts light.
 #[derive(new, Debug)]
 pub struct Dielectric {
     index_of_refraction: f64,
@@ -94,9 +140,47 @@ impl Material for Dielectric {
                 unit_direction.refract(hit_record.normal, eta_ratio)
             };
 
-        Scatter {
+        Scatter::Specular {
             attenuation: Color::new(1., 1., 1.),
-            ray: Some(Ray::new(hit_record.point, scatter_direction)),
+            ray: Ray::new(hit_record.point, scatter_direction, ray.time),
         }
     }
 }
+
+/// A material that emits light uniformly in all directions and does not scatter any.
+#[derive(new, Debug)]
+pub struct DiffuseLight {
+    emit: Arc<dyn Texture>,
+}
+impl Material for DiffuseLight {
+    fn scatter(&self, _rng: &mut UsedRng, _ray: &Ray, _hit_record: &HitRecord) -> Scatter {
+        Scatter::Absorbed
+    }
+
+    fn emitted(&self, hit_record: &HitRecord) -> Color {
+        self.emit
+            .value(hit_record.u, hit_record.v, &hit_record.point)
+    }
+
+    fn is_emissive(&self) -> bool {
+        true
+    }
+}
+
+/// The material of the particles in a volume such as fog or smoke, which scatter light equally in
+/// all directions.
+#[derive(new, Debug)]
+pub struct Isotropic {
+    albedo: Arc<dyn Texture>,
+}
+impl Material for Isotropic {
+    fn scatter(&self, _rng: &mut UsedRng, _ray: &Ray, _hit_record: &HitRecord) -> Scatter {
+        Scatter::Pdf(Box::new(SpherePdf))
+    }
+
+    fn eval(&self, _ray: &Ray, hit_record: &HitRecord, _direction: &Vector) -> Color {
+        self.albedo
+            .value(hit_record.u, hit_record.v, &hit_record.point)
+            / (4. * PI)
+    }
+}
@@ -1,20 +1,33 @@
-use cgmath::{InnerSpace, Vector3, VectorSpace};
+//! Geometric types and vector math.
+
+use crate::{hittable::Hittable, UsedRng};
+use cgmath::{EuclideanSpace, InnerSpace, Vector3, VectorSpace};
 use derive_new::new;
+use easy_cast::Conv;
 use rand::{distributions::Uniform, prelude::Distribution, Rng};
 use roots::find_roots_quadratic;
-use std::ops::Range;
+use std::{
+    f64::consts::PI,
+    ops::{Range, RangeInclusive},
+};
 
 const NEAR_ZERO_THRESH: f64 = 1e-8;
 
+/// A location in space.
 pub type Point = cgmath::Point3<f64>;
+/// A direction or displacement in space.
 pub type Vector = cgmath::Vector3<f64>;
+/// An affine transformation of space, in homogeneous coordinates.
+pub type Matrix = cgmath::Matrix4<f64>;
 
+/// Additional vector operations needed for ray tracing.
 pub trait VectorExt:
     Sized + VectorSpace<Scalar = f64> + InnerSpace + std::ops::Neg<Output = Self>
 {
     /// A random vector, with each component chosen from a uniform distribution in the `range`.
     fn random<R: Rng>(rng: &mut R, range: Range<f64>) -> Self;
 
+    /// A random vector with each component in `[0, 1)`.
     fn random_unit_cube<R: Rng>(rng: &mut R) -> Self {
         Self::random(rng, 0.0..1.)
     }
@@ -37,6 +50,7 @@ pub trait VectorExt:
         Self::random_within_unit_sphere(rng).normalize()
     }
 
+    /// Returns a unit vector in a random direction on the same side as the `normal`.
     fn random_on_hemisphere<R: Rng>(rng: &mut R, normal: Self) -> Self {
         let unit = Self::random_unit(rng);
 
@@ -47,17 +61,24 @@ pub trait VectorExt:
         }
     }
 
-    // This will have the same length as this vector
+    /// Mirrors this vector about the `normal`, giving a vector with the same length.
     fn reflect(&self, normal: Self) -> Self;
 
-    // `eta_ratio` is the incident eta over the transmission eta.
-    // Both this and the normal vector should be unit length.
+    /// Refracts this vector through a surface with the `normal` according to Snell's law.
+    ///
+    /// The `eta_ratio` is the incident eta over the transmission eta.
+    /// Both this and the normal vector should be unit length.
     fn refract(&self, normal: Self, eta_ratio: f64) -> Self;
+
+    /// The mean of the vectors, of which there must be at least one.
     fn average(vectors: impl Iterator<Item = Self>) -> Self;
 
+    /// Applies the function to each component.
     fn map<T>(&self, f: impl Fn(f64) -> T) -> Vector3<T>;
+    /// Whether the function is true for every component.
     fn all(&self, f: impl Fn(f64) -> bool) -> bool;
 
+    /// Whether every component is very close to zero.
     fn near_zero(&self) -> bool;
 }
 impl VectorExt for Vector {
@@ -119,39 +140,324 @@ impl VectorExt for Vector {
 }
 
 #[derive(new)]
-pub struct DirectionVectors {
+pub(crate) struct DirectionVectors {
     pub u: Vector,
     pub v: Vector,
 }
 
 #[derive(new)]
-pub struct BasisVectors {
+pub(crate) struct BasisVectors {
     pub u: Vector,
     pub v: Vector,
     pub w: Vector,
 }
+impl BasisVectors {
+    /// An orthonormal basis with `w` pointing in the direction.
+    pub fn from_w(direction: Vector) -> Self {
+        let w = direction.normalize();
+        let other = if w.x.abs() > 0.9 {
+            Vector::unit_y()
+        } else {
+            Vector::unit_x()
+        };
+        let v = w.cross(other).normalize();
+        let u = w.cross(v);
+
+        Self::new(u, v, w)
+    }
+
+    /// Converts a vector with coordinates in this basis to the standard basis.
+    pub fn to_standard(&self, coordinates: Vector) -> Vector {
+        coordinates.x * self.u + coordinates.y * self.v + coordinates.z * self.w
+    }
+}
+
+/// A probability distribution of directions, which is used to choose random directions in which
+/// the most light is expected.
+pub trait Pdf {
+    /// The probability density with respect to solid angle of generating the `direction`.
+    fn value(&self, direction: &Vector) -> f64;
+
+    /// A random direction from the distribution, which is `None` only if the density is zero
+    /// everywhere.
+    fn generate(&self, rng: &mut UsedRng) -> Option<Vector>;
+}
+
+/// Directions on the hemisphere around a normal, with a density proportional to the cosine of the
+/// angle to the normal.
+pub struct CosinePdf {
+    basis: BasisVectors,
+}
+impl CosinePdf {
+    /// The distribution around the `normal`.
+    pub fn new(normal: Vector) -> Self {
+        Self {
+            basis: BasisVectors::from_w(normal),
+        }
+    }
+}
+impl Pdf for CosinePdf {
+    fn value(&self, direction: &Vector) -> f64 {
+        self.basis.w.dot(direction.normalize()).max(0.) / PI
+    }
+
+    /// Projects points chosen uniformly on the unit disk up onto the hemisphere.
+    fn generate(&self, rng: &mut UsedRng) -> Option<Vector> {
+        let phi = 2. * PI * rng.gen::<f64>();
+        let radius_squared = rng.gen::<f64>();
+        let radius = radius_squared.sqrt();
+
+        Some(self.basis.to_standard(Vector::new(
+            radius * phi.cos(),
+            radius * phi.sin(),
+            (1. - radius_squared).sqrt(),
+        )))
+    }
+}
+
+/// Directions spread evenly over the whole sphere.
+pub struct SpherePdf;
+impl Pdf for SpherePdf {
+    fn value(&self, _direction: &Vector) -> f64 {
+        1. / (4. * PI)
+    }
+
+    fn generate(&self, rng: &mut UsedRng) -> Option<Vector> {
+        Some(Vector::random_unit(rng))
+    }
+}
+
+/// Directions spread evenly within a cone around an axis.
+pub struct ConePdf {
+    basis: BasisVectors,
+    /// One minus the cosine of the half angle of the cone, which is two for the whole sphere.
+    size: f64,
+}
+impl ConePdf {
+    /// The cone around the `axis` of the `size`, given as one minus the cosine of its half angle
+    /// so that small cones are precise.
+    pub fn new(axis: Vector, size: f64) -> Self {
+        Self {
+            basis: BasisVectors::from_w(axis),
+            size,
+        }
+    }
+}
+impl Pdf for ConePdf {
+    fn value(&self, direction: &Vector) -> f64 {
+        if 1. - self.basis.w.dot(direction.normalize()) <= self.size {
+            1. / (2. * PI * self.size)
+        } else {
+            0.
+        }
+    }
+
+    fn generate(&self, rng: &mut UsedRng) -> Option<Vector> {
+        let phi = 2. * PI * rng.gen::<f64>();
+        let z = 1. - rng.gen::<f64>() * self.size;
+        let radius = (1. - z * z).max(0.).sqrt();
+
+        Some(
+            self.basis
+                .to_standard(Vector::new(radius * phi.cos(), radius * phi.sin(), z)),
+        )
+    }
+}
+
+/// Directions from a point towards an object, as sampled by the object.
+#[derive(new)]
+pub struct HittablePdf<'a> {
+    hittable: &'a dyn Hittable,
+    origin: Point,
+}
+impl Pdf for HittablePdf<'_> {
+    fn value(&self, direction: &Vector) -> f64 {
+        self.hittable.pdf_value(&self.origin, direction)
+    }
+
+    fn generate(&self, rng: &mut UsedRng) -> Option<Vector> {
+        self.hittable.random_direction(rng, &self.origin)
+    }
+}
+
+/// A mixture of two distributions, with the first being chosen with probability `weight`.
+#[derive(new)]
+pub struct MixturePdf<'a> {
+    first: &'a dyn Pdf,
+    second: &'a dyn Pdf,
+    weight: f64,
+}
+impl Pdf for MixturePdf<'_> {
+    fn value(&self, direction: &Vector) -> f64 {
+        self.weight * self.first.value(direction)
+            + (1. - self.weight) * self.second.value(direction)
+    }
 
-#[derive(new, Debug)]
+    fn generate(&self, rng: &mut UsedRng) -> Option<Vector> {
+        if rng.gen::<f64>() < self.weight {
+            self.first.generate(rng)
+        } else {
+            self.second.generate(rng)
+        }
+    }
+}
+
+/// A half-line starting at the origin, with points along it given by a parameter `t`.
+#[derive(new, Debug, Clone, Copy)]
 pub struct Ray {
     pub origin: Point,
     pub direction: Vector,
+    /// The moment at which the ray is traced, which determines where moving objects are.
+    pub time: f64,
 }
 impl Ray {
+    /// The point at the parameter `t`, measured in multiples of the direction.
     pub fn at(&self, t: f64) -> Point {
         self.origin + t * self.direction
     }
 }
 
+/// The running mean and variance of a sequence of numbers, using Welford's algorithm to avoid
+/// the loss of precision of summing squares.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct RunningStatistics {
+    pub count: usize,
+    pub mean: f64,
+    /// Sum of squared differences from the mean.
+    pub squared_deviations: f64,
+}
+impl RunningStatistics {
+    pub fn push(&mut self, x: f64) {
+        self.count += 1;
+        let delta = x - self.mean;
+        self.mean += delta / f64::conv(self.count);
+        self.squared_deviations += delta * (x - self.mean);
+    }
+
+    pub fn mean(&self) -> f64 {
+        self.mean
+    }
+
+    /// The unbiased sample variance, which needs at least two numbers.
+    pub fn variance(&self) -> f64 {
+        self.squared_deviations / f64::conv(self.count - 1)
+    }
+
+    /// The standard error of the mean, that is the expected deviation of the mean from the true mean.
+    pub fn standard_error(&self) -> f64 {
+        (self.variance() / f64::conv(self.count)).sqrt()
+    }
+}
+
+/// An axis-aligned bounding box, given by its minimum and maximum corners.
+#[derive(new, Debug, Clone, Copy)]
+pub struct Aabb {
+    pub min: Point,
+    pub max: Point,
+}
+impl Aabb {
+    /// Creates the smallest box containing both points, which can be any two opposite corners.
+    pub fn from_corners(a: Point, b: Point) -> Self {
+        Self::new(
+            Point::new(a.x.min(b.x), a.y.min(b.y), a.z.min(b.z)),
+            Point::new(a.x.max(b.x), a.y.max(b.y), a.z.max(b.z)),
+        )
+    }
+
+    /// A box that contains nothing, which is the identity for [`Aabb::union`].
+    pub fn empty() -> Self {
+        Self::new(
+            Point::new(f64::INFINITY, f64::INFINITY, f64::INFINITY),
+            Point::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY),
+        )
+    }
+
+    /// The smallest box containing both boxes.
+    pub fn union(&self, other: &Self) -> Self {
+        Self::new(
+            Point::new(
+                self.min.x.min(other.min.x),
+                self.min.y.min(other.min.y),
+                self.min.z.min(other.min.z),
+            ),
+            Point::new(
+                self.max.x.max(other.max.x),
+                self.max.y.max(other.max.y),
+                self.max.z.max(other.max.z),
+            ),
+        )
+    }
+
+    /// The box widened to a small thickness along any axis where it is flat, so that rays are not
+    /// missed due to rounding when passing through it.
+    pub fn padded(&self) -> Self {
+        const MIN_THICKNESS: f64 = 1e-4;
+
+        let (mut min, mut max) = (self.min, self.max);
+        for axis in 0..3 {
+            let padding = (MIN_THICKNESS - (max[axis] - min[axis])).max(0.) / 2.;
+            min[axis] -= padding;
+            max[axis] += padding;
+        }
+        Self::new(min, max)
+    }
+
+    /// The center of the box.
+    pub fn centroid(&self) -> Point {
+        self.min.midpoint(self.max)
+    }
+
+    /// The total area of the six faces, which is zero for empty boxes.
+    pub fn surface_area(&self) -> f64 {
+        let extent = self.max - self.min;
+        if extent.all(|x| x >= 0.) {
+            2. * (extent.x * extent.y + extent.y * extent.z + extent.z * extent.x)
+        } else {
+            0.
+        }
+    }
+
+    /// Whether the ray passes through the box for some parameter in the range.
+    pub fn hit(&self, ray: &Ray, t_range: &RangeInclusive<f64>) -> bool {
+        self.overlap(ray, t_range).is_some()
+    }
+
+    /// The part of the range for which the ray is inside the box, using the slab method.
+    pub fn overlap(&self, ray: &Ray, t_range: &RangeInclusive<f64>) -> Option<RangeInclusive<f64>> {
+        let (mut t_min, mut t_max) = (*t_range.start(), *t_range.end());
+
+        for axis in 0..3 {
+            let inverse_direction = 1. / ray.direction[axis];
+            let t0 = (self.min[axis] - ray.origin[axis]) * inverse_direction;
+            let t1 = (self.max[axis] - ray.origin[axis]) * inverse_direction;
+            let (t0, t1) = if inverse_direction < 0. {
+                (t1, t0)
+            } else {
+                (t0, t1)
+            };
+
+            t_min = t_min.max(t0);
+            t_max = t_max.min(t1);
+            if t_max < t_min {
+                return None;
+            }
+        }
+
+        Some(t_min..=t_max)
+    }
+}
+
 #[derive(Debug)]
-pub enum ParabolaRoots {
+pub(crate) enum ParabolaRoots {
     None,
     One(f64),
     // In order of absolute value
     Two(f64, f64),
 }
 
+/// The quadratic polynomial `a x^2 + b x + c`.
 #[derive(new)]
-pub struct Parabola {
+pub(crate) struct Parabola {
     a: f64,
     b: f64,
     c: f64,
@@ -163,9 +469,11 @@ impl Parabola {
         match find_roots_quadratic(self.a, self.b, self.c) {
             Roots::One(r) => ParabolaRoots::One(r[0]),
             Roots::Two(r) => {
-                let roots = std::cmp::minmax_by(r[0], r[1], |x, y| x.partial_cmp(y).unwrap());
-
-                ParabolaRoots::Two(roots[0], roots[1])
+                if r[0] <= r[1] {
+                    ParabolaRoots::Two(r[0], r[1])
+                } else {
+                    ParabolaRoots::Two(r[1], r[0])
+                }
             }
             _ => ParabolaRoots::None,
         }
@@ -0,0 +1,217 @@
+//! Triangle meshes and loading them from model files.
+
+use crate::{
+    bvh::BvhNode,
+    hittable::{HitRecord, Hittable, Light, Triangle},
+    image::Color,
+    material::{Dielectric, DiffuseLight, Lambertian, Material, Metal},
+    math::{Aabb, Point, Ray, Vector},
+    reader::{read_image, ReadError},
+    texture::{ImageTexture, SolidColor, Texture},
+};
+use easy_cast::Conv;
+use std::{
+    cell::RefCell,
+    fs::File,
+    io::BufReader,
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use thiserror::Error;
+
+/// The reason a mesh file could not be loaded.
+#[derive(Debug, Error)]
+pub enum MeshError {
+    #[error("could not read OBJ file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("could not load OBJ file: {0}")]
+    Obj(tobj::LoadError),
+    #[error("could not load MTL materials: {0}")]
+    Mtl(tobj::LoadError),
+    #[error("could not load texture image `{path}`: {error}")]
+    Texture { path: PathBuf, error: ReadError },
+}
+
+/// A collection of triangles, typically loaded from a model file.
+pub struct Mesh {
+    triangles: BvhNode<Triangle>,
+    files: Vec<PathBuf>,
+}
+impl Mesh {
+    /// A mesh made of the triangles, which are put into a bounding volume hierarchy.
+    pub fn new(triangles: Vec<Triangle>) -> Self {
+        Self {
+            triangles: BvhNode::new(triangles),
+            files: Vec::new(),
+        }
+    }
+
+    /// Loads a Wavefront OBJ file, with polygons being triangulated.
+    ///
+    /// Any MTL materials referenced by the file are mapped onto the closest of the available
+    /// materials, with the `default_material` being used for faces without one.
+    pub fn load_obj(path: &Path, default_material: Arc<dyn Material>) -> Result<Self, MeshError> {
+        let file = File::open(path)?;
+        let directory = path.parent().unwrap_or(Path::new(""));
+
+        // Keep track of the MTL files as they are named by the OBJ file
+        let mtl_files = RefCell::new(Vec::new());
+        let (models, mtl_materials) = tobj::load_obj_buf(
+            &mut BufReader::new(file),
+            &tobj::LoadOptions {
+                single_index: true,
+                triangulate: true,
+                ignore_points: true,
+                ignore_lines: true,
+            },
+            |mtl_path| {
+                let mtl_path = directory.join(mtl_path);
+                let materials = tobj::load_mtl(&mtl_path);
+                if materials.is_ok() {
+                    mtl_files.borrow_mut().push(mtl_path);
+                }
+                materials
+            },
+        )
+        .map_err(MeshError::Obj)?;
+        let mut files = vec![path.to_path_buf()];
+        files.append(&mut mtl_files.into_inner());
+
+        // Only fail on missing materials if they are actually needed
+        let materials = match mtl_materials {
+            Ok(materials) => materials
+                .iter()
+                .map(|m| Self::convert_material(m, directory, &mut files))
+                .collect::<Result<Vec<_>, _>>()?,
+            Err(e) => {
+                if models.iter().any(|m| m.mesh.material_id.is_some()) {
+                    return Err(MeshError::Mtl(e));
+                }
+                Vec::new()
+            }
+        };
+
+        let mut triangles = Vec::new();
+        for model in models {
+            let mesh = &model.mesh;
+            let material = mesh
+                .material_id
+                .and_then(|id| materials.get(id))
+                .unwrap_or(&default_material);
+
+            let point = |i: usize| {
+                let p = &mesh.positions[3 * i..3 * i + 3];
+                Point::new(p[0].into(), p[1].into(), p[2].into())
+            };
+            let normal = |i: usize| {
+                let n = &mesh.normals[3 * i..3 * i + 3];
+                Vector::new(n[0].into(), n[1].into(), n[2].into())
+            };
+            let surface_coordinates = |i: usize| {
+                let uv = &mesh.texcoords[2 * i..2 * i + 2];
+                (uv[0].into(), uv[1].into())
+            };
+
+            for face in mesh.indices.chunks_exact(3) {
+                let face = [face[0], face[1], face[2]].map(usize::conv);
+
+                let mut triangle = Triangle::new(face.map(point), material.clone());
+                if !mesh.normals.is_empty() {
+                    triangle = triangle.with_normals(face.map(normal));
+                }
+                if !mesh.texcoords.is_empty() {
+                    triangle = triangle.with_surface_coordinates(face.map(surface_coordinates));
+                }
+                triangles.push(triangle);
+            }
+        }
+
+        Ok(Self {
+            files,
+            ..Self::new(triangles)
+        })
+    }
+
+    /// The files that the mesh was loaded from, which are the OBJ file along with any MTL files
+    /// and texture images that it uses.
+    pub fn files(&self) -> &[PathBuf] {
+        &self.files
+    }
+
+    /// Chooses the closest material to an MTL material, which are designed for a Phong shading model.
+    ///
+    /// Any texture image that is loaded is added to the `files`.
+    fn convert_material(
+        material: &tobj::Material,
+        directory: &Path,
+        files: &mut Vec<PathBuf>,
+    ) -> Result<Arc<dyn Material>, MeshError> {
+        let color =
+            |c: Option<[f32; 3]>| c.map(|c| Color::new(c[0].into(), c[1].into(), c[2].into()));
+
+        // Emission is not a standard parameter, but is commonly used
+        let emission = material.unknown_param.get("Ke").and_then(|ke| {
+            let c = ke
+                .split_whitespace()
+                .map(|x| x.parse::<f64>())
+                .collect::<Result<Vec<_>, _>>()
+                .ok()?;
+            (c.len() == 3 && c.iter().any(|x| *x > 0.)).then(|| Color::new(c[0], c[1], c[2]))
+        });
+        if let Some(emission) = emission {
+            return Ok(Arc::new(DiffuseLight::new(Arc::new(SolidColor::new(
+                emission,
+            )))));
+        }
+
+        // Transparent materials or those using one of the refraction illumination models are glass
+        let illumination = material.illumination_model.unwrap_or(2);
+        if material.dissolve.is_some_and(|d| d < 1.) || [4, 6, 7, 9].contains(&illumination) {
+            return Ok(Arc::new(Dielectric::new(
+                material.optical_density.map_or(1.5, f64::from),
+            )));
+        }
+
+        // Those using one of the reflection illumination models are metal
+        if [3, 5].contains(&illumination) {
+            // Convert the Phong exponent to a roughness, which serves well enough as the fuzz
+            let fuzz = material
+                .shininess
+                .map_or(0., |ns| (2. / (f64::from(ns) + 2.)).sqrt());
+            let albedo = color(material.specular).unwrap_or(Color::new(1., 1., 1.));
+            return Ok(Arc::new(Metal::from_color(albedo, fuzz)));
+        }
+
+        // Everything else is diffuse
+        let albedo: Arc<dyn Texture> = match &material.diffuse_texture {
+            Some(file) => {
+                let path = directory.join(file);
+                match read_image(&path) {
+                    Ok(image) => {
+                        files.push(path);
+                        Arc::new(ImageTexture::new(image))
+                    }
+                    Err(error) => return Err(MeshError::Texture { path, error }),
+                }
+            }
+            None => Arc::new(SolidColor::new(
+                color(material.diffuse).unwrap_or(Color::new(0.5, 0.5, 0.5)),
+            )),
+        };
+        Ok(Arc::new(Lambertian::new(albedo)))
+    }
+}
+impl Hittable for Mesh {
+    fn hit(&self, ray: &Ray, t_range: &RangeInclusive<f64>) -> Option<HitRecord<'_>> {
+        self.triangles.hit(ray, t_range)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.triangles.bounding_box()
+    }
+
+    fn collect_lights<'a>(&'a self, lights: &mut Vec<Light<'a>>) {
+        self.triangles.collect_lights(lights)
+    }
+}
@@ -0,0 +1,195 @@
+//! Reading of image files, for use as textures and environment maps.
+
+use crate::image::{Color, Image, Size};
+use easy_cast::Conv;
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, Read},
+    path::Path,
+};
+use thiserror::Error;
+
+/// The reason an image file could not be read.
+#[derive(Debug, Error)]
+pub enum ReadError {
+    #[error("unsupported image file extension `{0}`, expected one of: png, hdr")]
+    UnsupportedExtension(String),
+    #[error("the image file name has no extension to determine the format from")]
+    MissingExtension,
+    #[error("could not read image file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("could not decode PNG image: {0}")]
+    Png(#[from] png::DecodingError),
+    #[error("invalid Radiance HDR image: {0}")]
+    Hdr(&'static str),
+}
+
+/// Reads an image file, with the format being determined by the extension.
+///
+/// The colors of the returned image are linear, so any transfer function is undone.
+pub fn read_image(path: &Path) -> Result<Image, ReadError> {
+    let extension = path
+        .extension()
+        .ok_or(ReadError::MissingExtension)?
+        .to_string_lossy();
+
+    match extension.to_lowercase().as_str() {
+        "png" => read_png(path),
+        "hdr" => read_hdr(path),
+        _ => Err(ReadError::UnsupportedExtension(extension.into_owned())),
+    }
+}
+
+/// The inverse of the sRGB transfer function, which converts display values to linear values.
+fn srgb_to_linear(x: f64) -> f64 {
+    if x <= 0.04045 {
+        x / 12.92
+    } else {
+        ((x + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn read_png(path: &Path) -> Result<Image, ReadError> {
+    let mut decoder = png::Decoder::new(BufReader::new(File::open(path)?));
+
+    // Always get 8-bit channels with any palette expanded
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info()?;
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buffer)?;
+    let bytes = &buffer[..info.buffer_size()];
+
+    let channel = |x: u8| srgb_to_linear(f64::from(x) / f64::from(u8::MAX));
+    let pixel_data = match info.color_type {
+        png::ColorType::Grayscale | png::ColorType::GrayscaleAlpha => bytes
+            .chunks(info.color_type.samples())
+            .map(|p| Color::new(channel(p[0]), channel(p[0]), channel(p[0])))
+            .collect(),
+        _ => bytes
+            .chunks(info.color_type.samples())
+            .map(|p| Color::new(channel(p[0]), channel(p[1]), channel(p[2])))
+            .collect(),
+    };
+
+    Ok(Image::new(
+        Size::new(usize::conv(info.width), usize::conv(info.height)),
+        pixel_data,
+    ))
+}
+
+/// Converts the shared exponent RGBE representation to a linear color.
+fn rgbe_to_color([r, g, b, e]: [u8; 4]) -> Color {
+    if e == 0 {
+        return Color::new(0., 0., 0.);
+    }
+
+    // Each mantissa is the middle of the range of values that round down to it
+    let scale = (f64::from(e) - 136.).exp2();
+    Color::new(f64::from(r) + 0.5, f64::from(g) + 0.5, f64::from(b) + 0.5) * scale
+}
+
+/// Reads one component of an RGBE scanline using the run length encoding of the new Radiance format.
+fn read_hdr_component(
+    reader: &mut impl Read,
+    pixels: &mut [[u8; 4]],
+    component: usize,
+) -> Result<(), ReadError> {
+    let mut i = 0;
+    while i < pixels.len() {
+        let mut count = [0; 2];
+        reader.read_exact(&mut count[..1])?;
+
+        // Counts above 128 are runs of a single value, and the others are literal spans
+        let (length, run) = match count[0] {
+            c @ 129.. => (usize::from(c - 128), true),
+            c => (usize::from(c), false),
+        };
+        if length == 0 || i + length > pixels.len() {
+            return Err(ReadError::Hdr("bad scanline encoding"));
+        }
+        if run {
+            reader.read_exact(&mut count[1..])?;
+        }
+        for pixel in &mut pixels[i..i + length] {
+            if !run {
+                reader.read_exact(&mut count[1..])?;
+            }
+            pixel[component] = count[1];
+        }
+        i += length;
+    }
+
+    Ok(())
+}
+
+fn read_hdr(path: &Path) -> Result<Image, ReadError> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut line = String::new();
+    let mut read_line = |reader: &mut BufReader<File>| -> Result<String, ReadError> {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(ReadError::Hdr("the header is incomplete"));
+        }
+        Ok(line.trim_end().to_string())
+    };
+
+    // Header
+    if !read_line(&mut reader)?.starts_with("#?") {
+        return Err(ReadError::Hdr("missing `#?RADIANCE` identifier"));
+    }
+    loop {
+        let line = read_line(&mut reader)?;
+        if line.is_empty() {
+            break;
+        }
+        if let Some(format) = line.strip_prefix("FORMAT=") {
+            if format != "32-bit_rle_rgbe" {
+                return Err(ReadError::Hdr("only the RGBE pixel format is supported"));
+            }
+        }
+    }
+
+    // Only the usual orientation of rows from the top down is supported
+    let resolution = read_line(&mut reader)?;
+    let size = match resolution.split_whitespace().collect::<Vec<_>>()[..] {
+        ["-Y", height, "+X", width] => width.parse().ok().zip(height.parse().ok()),
+        _ => None,
+    };
+    let Some((width, height)) = size.filter(|&(w, h): &(usize, usize)| w > 0 && h > 0) else {
+        return Err(ReadError::Hdr("unsupported image size or orientation"));
+    };
+
+    if width.checked_mul(height).is_none() {
+        return Err(ReadError::Hdr("the image is too large"));
+    }
+
+    // The pixels are only stored as they are decoded, so that a corrupt size cannot cause a huge
+    // allocation
+    let mut pixel_data = Vec::new();
+    let mut scanline = Vec::new();
+    for _ in 0..height {
+        let mut start = [0; 4];
+        reader.read_exact(&mut start)?;
+
+        // Run length encoded scanlines start with a marker giving the width, and others are flat
+        if start[..2] == [2, 2] && start[2] < 128 && (8..0x8000).contains(&width) {
+            if usize::from(u16::from_be_bytes([start[2], start[3]])) != width {
+                return Err(ReadError::Hdr("scanline width does not match the image"));
+            }
+            scanline.resize(width, [0; 4]);
+            for component in 0..4 {
+                read_hdr_component(&mut reader, &mut scanline, component)?;
+            }
+            pixel_data.extend(scanline.iter().copied().map(rgbe_to_color));
+        } else {
+            pixel_data.push(rgbe_to_color(start));
+            for _ in 1..width {
+                let mut pixel = [0; 4];
+                reader.read_exact(&mut pixel)?;
+                pixel_data.push(rgbe_to_color(pixel));
+            }
+        }
+    }
+
+    Ok(Image::new(Size::new(width, height), pixel_data.into()))
+}
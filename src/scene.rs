@@ -0,0 +1,837 @@
+//! Loading of scene description files.
+//!
+//! A scene file is a plain text file with one statement per line, and `#` starting a comment
+//! that runs to the end of the line.
+//! Each statement starts with a keyword, possibly followed by some positional arguments,
+//! and then any number of `key=value` parameters in any order.
+//! Vectors, points and colors are written as three comma-separated numbers without spaces,
+//! for example `0.5,0.7,1`.
+//!
+//! The statements are:
+//!
+//! - `camera [look_from=13,2,3] [look_at=0,0,0] [up=0,1,0] [vfov=20] [defocus_angle=0.6] [focus_distance=10] [shutter_open=0] [shutter_close=0] [samples=500] [min_samples=16] [noise_threshold=<number>] [max_depth=50]`
+//! - `background gradient [bottom=1,1,1] [top=0.5,0.7,1]` or `background solid color=<color>`
+//! - `background image file=<path> [rotation=0] [intensity=1]`
+//! - `background sky [sun=1,1,1] [turbidity=3] [sun_diameter=0.53] [intensity=1]`
+//! - `texture <name> solid color=<color>`
+//! - `texture <name> checker even=<texture> odd=<texture> [scale=1]`
+//! - `texture <name> image file=<path>`
+//! - `texture <name> noise|turbulence|marble [scale=1]`
+//! - `material <name> lambertian albedo=<texture>`
+//! - `material <name> metal albedo=<texture> [fuzz=0]`
+//! - `material <name> dielectric ior=<number>`
+//! - `material <name> light emit=<texture>`
+//! - `material <name> isotropic albedo=<texture>`
+//! - `sphere center=<point> radius=<number> material=<name> [center_end=<point>]`
+//! - `triangle a=<point> b=<point> c=<point> material=<name>`
+//! - `quad corner=<point> u=<vector> v=<vector> material=<name>`
+//! - `disk center=<point> normal=<vector> radius=<number> material=<name>`
+//! - `box a=<point> b=<point> material=<name>`
+//! - `mesh file=<path> [material=<name>]`
+//! - `volume file=<path> a=<point> b=<point> [absorption=0] [scattering=1] [emission=0,0,0]`
+//!
+//! Any object can also be given `scale=<vector>`, `rotate=<vector>` and `translate=<vector>`
+//! parameters, which are applied in that order, with the rotation being about the x, y and z axes
+//! in that order by the given angles in degrees.
+//! A sphere with a `center_end` moves in a straight line from its `center` at time zero to there
+//! at time one, and is blurred along its path while the camera shutter is open.
+//! Any object other than a volume can also be given a `density=<number>`, which fills it with a
+//! fog-like volume that scatters light off of particles made of its material, usually an
+//! `isotropic` one.
+//! Each mesh file is only loaded once for each material, and is shared by all of its uses.
+//! A volume stretches the density grid in a [grid file](crate::volume) over the box with opposite
+//! corners `a` and `b`, and absorbs, scatters and emits light in proportion to the density.
+//!
+//! Wherever a texture is expected either the name of a texture or a color can be given, with the
+//! latter being a solid color texture.
+//! Image, mesh and grid file paths are relative to the directory containing the scene file.
+//! Images are PNG or Radiance HDR files, and an image background is an equirectangular panorama
+//! turned by `rotation` degrees about the vertical axis and scaled in brightness by `intensity`.
+//! A sky background is a physically based daylight sky with the sun in the direction `sun`, which
+//! is hazier with a higher `turbidity`, and the sun has an angular diameter in degrees.
+//! Meshes are loaded from Wavefront OBJ files, with any MTL materials being mapped onto the
+//! available materials, and the `material` being used for faces without one.
+//!
+//! Textures and materials must be defined before they are used.
+//! The camera and background may each be given at most once, and default to the
+//! final scene of the first book if omitted.
+
+use crate::{
+    background::{Background, EnvironmentMap, Gradient, Sky, Solid},
+    camera::CameraSettings,
+    hittable::{
+        ConstantMedium, Cuboid, Disk, Hittable, MovingSphere, Quad, Sphere, Transformed, Triangle,
+    },
+    image::{Color, Image},
+    material::{Dielectric, DiffuseLight, Isotropic, Lambertian, Material, Metal},
+    math::{Matrix, Point, Vector, VectorExt},
+    mesh::{Mesh, MeshError},
+    reader::{read_image, ReadError},
+    texture::{Checker, ImageTexture, NoiseStyle, NoiseTexture, Perlin, SolidColor, Texture},
+    volume::{DensityGrid, GridVolume, VolumeError},
+    UsedRng,
+};
+use cgmath::{Deg, ElementWise, InnerSpace, Zero};
+use itertools::iproduct;
+use rand::Rng;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use thiserror::Error;
+
+/// The reason a scene file statement could not be understood.
+#[derive(Debug, Error)]
+pub enum SceneErrorKind {
+    #[error(
+        "unknown statement `{0}`, expected one of: camera, background, texture, material, sphere, triangle, quad, disk, box, mesh, volume"
+    )]
+    UnknownStatement(String),
+    #[error("unknown {what} type `{name}`, expected one of: {expected}")]
+    UnknownType {
+        what: &'static str,
+        name: String,
+        expected: &'static str,
+    },
+    #[error("expected {0}")]
+    MissingArgument(&'static str),
+    #[error("unexpected argument `{0}`")]
+    UnexpectedArgument(String),
+    #[error("missing parameter `{0}`")]
+    MissingParameter(&'static str),
+    #[error("unknown parameter `{0}`")]
+    UnknownParameter(String),
+    #[error("parameter `{0}` is given more than once")]
+    DuplicateParameter(String),
+    #[error("invalid number `{value}` for parameter `{key}`")]
+    InvalidNumber { key: &'static str, value: String },
+    #[error("parameter `{0}` must be at least one")]
+    ZeroCount(&'static str),
+    #[error("invalid value {value} for parameter `{key}`, expected {expected}")]
+    OutOfRange {
+        key: &'static str,
+        value: f64,
+        expected: &'static str,
+    },
+    #[error(
+        "invalid vector `{value}` for parameter `{key}`, expected three comma-separated numbers like `1,0.5,2`"
+    )]
+    InvalidVector { key: &'static str, value: String },
+    #[error("unknown material `{0}`, materials must be defined before they are used")]
+    UnknownMaterial(String),
+    #[error("material `{0}` is already defined")]
+    DuplicateMaterial(String),
+    #[error("unknown texture `{0}`, textures must be defined before they are used")]
+    UnknownTexture(String),
+    #[error("texture `{0}` is already defined")]
+    DuplicateTexture(String),
+    #[error("could not load image `{path}`: {error}")]
+    Image { path: PathBuf, error: ReadError },
+    #[error("could not load mesh `{path}`: {error}")]
+    Mesh { path: PathBuf, error: MeshError },
+    #[error("could not load density grid `{path}`: {error}")]
+    Volume { path: PathBuf, error: VolumeError },
+    #[error("the {0} is already defined")]
+    AlreadyDefined(&'static str),
+    #[error("a volume cannot be given a `density`, since only surfaces can be filled with fog")]
+    VolumeDensity,
+    #[error("the transform cannot be undone, which happens when scaling by zero")]
+    SingularTransform,
+}
+
+/// The reason a scene file could not be loaded.
+#[derive(Debug, Error)]
+pub enum SceneError {
+    #[error("could not read scene file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("line {line}: {kind}\n{line:>5} | {text}")]
+    Statement {
+        line: usize,
+        text: String,
+        kind: SceneErrorKind,
+    },
+}
+
+/// Everything needed to render a scene, apart from the image settings.
+pub struct Scene {
+    /// Where the camera is and how it samples the scene.
+    pub camera: CameraSettings,
+    /// What is seen by rays that escape the scene.
+    pub background: Box<dyn Background>,
+    /// All of the objects in the scene.
+    pub objects: Vec<Box<dyn Hittable>>,
+    /// Every file that was loaded for the scene, such as images and meshes, apart from the scene
+    /// file itself.
+    pub files: Vec<PathBuf>,
+}
+impl Scene {
+    /// Reads and parses a scene file.
+    pub fn load(path: &Path, rng: &mut UsedRng) -> Result<Self, SceneError> {
+        Self::parse(
+            &std::fs::read_to_string(path)?,
+            path.parent().unwrap_or(Path::new("")),
+            rng,
+        )
+    }
+
+    /// Parses the text of a scene file, with any files referred to by the scene being relative to
+    /// the `directory`.
+    ///
+    /// The `rng` is used for anything random in the scene, such as noise textures.
+    pub fn parse(source: &str, directory: &Path, rng: &mut UsedRng) -> Result<Self, SceneError> {
+        let mut builder = SceneBuilder::new(directory, rng);
+
+        for (index, text) in source.lines().enumerate() {
+            let code = text.split('#').next().unwrap_or_default();
+            if code.trim().is_empty() {
+                continue;
+            }
+
+            builder
+                .statement(code)
+                .map_err(|kind| SceneError::Statement {
+                    line: index + 1,
+                    text: text.to_string(),
+                    kind,
+                })?;
+        }
+
+        Ok(builder.build())
+    }
+
+    /// Generates the final scene of the first book, with random little spheres around three large ones.
+    pub fn random_spheres(rng: &mut UsedRng) -> Self {
+        let ground: Arc<dyn Material> = Arc::new(Lambertian::from_color(Color::new(0.5, 0.5, 0.5)));
+        let glass: Arc<dyn Material> = Arc::new(Dielectric::new(1.5));
+
+        let mut objects: Vec<Box<dyn Hittable>> = vec![
+            // Large ground sphere
+            Box::new(Sphere::new(Point::new(0., -1000., 0.), 1000., ground)),
+        ];
+
+        // Add random little spheres
+        let gap_point = Point::new(4., 0.2, 0.);
+        for (a, b) in iproduct!(-11..11, -11..11) {
+            let center = Point::new(
+                f64::from(a) + 0.9 * rng.gen::<f64>(),
+                0.2,
+                f64::from(b) + 0.9 * rng.gen::<f64>(),
+            );
+
+            if (center - gap_point).magnitude() > 0.9 {
+                objects.push(Box::new(Sphere::new(
+                    center,
+                    0.2,
+                    match rng.gen::<f64>() {
+                        x if x < 0.8 => {
+                            // The squaring here ensures darker colors
+                            let color = Color::random_unit_cube(rng)
+                                .mul_element_wise(Color::random_unit_cube(rng));
+                            Arc::new(Lambertian::from_color(color))
+                        }
+                        x if x < 0.95 => {
+                            let color = Color::random(rng, 0.5..1.);
+                            Arc::new(Metal::from_color(color, 0.5 * rng.gen::<f64>()))
+                        }
+                        _ => glass.clone(),
+                    },
+                )))
+            }
+        }
+
+        // Add constant large spheres
+        objects.extend::<[Box<dyn Hittable>; 3]>([
+            // Glass
+            Box::new(Sphere::new(Point::new(0., 1., 0.), 1., glass)),
+            // Solid
+            Box::new(Sphere::new(
+                Point::new(-4., 1., 0.),
+                1.,
+                Arc::new(Lambertian::from_color(Color::new(0.4, 0.2, 0.1))),
+            )),
+            // Metal
+            Box::new(Sphere::new(
+                Point::new(4., 1., 0.),
+                1.,
+                Arc::new(Metal::from_color(Color::new(0.7, 0.6, 0.5), 0.)),
+            )),
+        ]);
+
+        Self {
+            camera: CameraSettings::default(),
+            background: Box::new(Gradient::default()),
+            objects,
+            files: Vec::new(),
+        }
+    }
+}
+
+/// A value that can be given for a statement parameter.
+trait ParameterValue: Sized {
+    fn parse(key: &'static str, value: &str) -> Result<Self, SceneErrorKind>;
+}
+impl ParameterValue for f64 {
+    fn parse(key: &'static str, value: &str) -> Result<Self, SceneErrorKind> {
+        value.parse().map_err(|_| SceneErrorKind::InvalidNumber {
+            key,
+            value: value.to_string(),
+        })
+    }
+}
+impl ParameterValue for usize {
+    fn parse(key: &'static str, value: &str) -> Result<Self, SceneErrorKind> {
+        value.parse().map_err(|_| SceneErrorKind::InvalidNumber {
+            key,
+            value: value.to_string(),
+        })
+    }
+}
+impl ParameterValue for Vector {
+    fn parse(key: &'static str, value: &str) -> Result<Self, SceneErrorKind> {
+        let invalid = || SceneErrorKind::InvalidVector {
+            key,
+            value: value.to_string(),
+        };
+
+        let components = value
+            .split(',')
+            .map(|c| c.parse::<f64>().map_err(|_| invalid()))
+            .collect::<Result<Vec<_>, _>>()?;
+        match components[..] {
+            [x, y, z] => Ok(Self::new(x, y, z)),
+            _ => Err(invalid()),
+        }
+    }
+}
+impl ParameterValue for Point {
+    fn parse(key: &'static str, value: &str) -> Result<Self, SceneErrorKind> {
+        Vector::parse(key, value).map(|v| Self::new(v.x, v.y, v.z))
+    }
+}
+impl ParameterValue for String {
+    fn parse(_key: &'static str, value: &str) -> Result<Self, SceneErrorKind> {
+        Ok(value.to_string())
+    }
+}
+
+/// A single statement split into its keyword, positional arguments, and parameters.
+struct Statement<'a> {
+    keyword: &'a str,
+    arguments: std::vec::IntoIter<&'a str>,
+    parameters: HashMap<&'a str, &'a str>,
+}
+impl<'a> Statement<'a> {
+    fn new(code: &'a str) -> Result<Self, SceneErrorKind> {
+        let mut tokens = code.split_whitespace();
+        let keyword = tokens.next().unwrap_or_default();
+
+        let mut arguments = Vec::new();
+        let mut parameters = HashMap::new();
+        for token in tokens {
+            match token.split_once('=') {
+                Some((key, value)) => {
+                    if parameters.insert(key, value).is_some() {
+                        return Err(SceneErrorKind::DuplicateParameter(key.to_string()));
+                    }
+                }
+                None => arguments.push(token),
+            }
+        }
+
+        Ok(Self {
+            keyword,
+            arguments: arguments.into_iter(),
+            parameters,
+        })
+    }
+
+    /// Takes the next positional argument, which is described by `what` if it is missing.
+    fn argument(&mut self, what: &'static str) -> Result<&'a str, SceneErrorKind> {
+        self.arguments
+            .next()
+            .ok_or(SceneErrorKind::MissingArgument(what))
+    }
+
+    fn optional<T: ParameterValue>(
+        &mut self,
+        key: &'static str,
+    ) -> Result<Option<T>, SceneErrorKind> {
+        self.parameters
+            .remove(key)
+            .map(|value| T::parse(key, value))
+            .transpose()
+    }
+
+    fn required<T: ParameterValue>(&mut self, key: &'static str) -> Result<T, SceneErrorKind> {
+        self.optional(key)?
+            .ok_or(SceneErrorKind::MissingParameter(key))
+    }
+
+    fn or<T: ParameterValue>(
+        &mut self,
+        key: &'static str,
+        default: T,
+    ) -> Result<T, SceneErrorKind> {
+        Ok(self.optional(key)?.unwrap_or(default))
+    }
+
+    /// Passes the parameter value through the builder method `set` if the parameter is present.
+    fn update<B, T: ParameterValue>(
+        &mut self,
+        key: &'static str,
+        builder: B,
+        set: impl FnOnce(B, T) -> B,
+    ) -> Result<B, SceneErrorKind> {
+        Ok(match self.optional(key)? {
+            Some(value) => set(builder, value),
+            None => builder,
+        })
+    }
+
+    /// Like `update` for a count that must be at least one.
+    fn update_count<B>(
+        &mut self,
+        key: &'static str,
+        builder: B,
+        set: impl FnOnce(B, usize) -> B,
+    ) -> Result<B, SceneErrorKind> {
+        match self.optional(key)? {
+            Some(0) => Err(SceneErrorKind::ZeroCount(key)),
+            Some(count) => Ok(set(builder, count)),
+            None => Ok(builder),
+        }
+    }
+
+    /// A number that must be finite and greater than zero, if the parameter is present.
+    fn positive(&mut self, key: &'static str) -> Result<Option<f64>, SceneErrorKind> {
+        match self.optional::<f64>(key)? {
+            Some(value) if !(value.is_finite() && value > 0.) => Err(SceneErrorKind::OutOfRange {
+                key,
+                value,
+                expected: "a finite number greater than zero",
+            }),
+            value => Ok(value),
+        }
+    }
+
+    /// A number that must be finite and not negative, or the `default` if the parameter is absent.
+    fn non_negative_or(&mut self, key: &'static str, default: f64) -> Result<f64, SceneErrorKind> {
+        let value = self.or(key, default)?;
+        if value.is_finite() && value >= 0. {
+            Ok(value)
+        } else {
+            Err(SceneErrorKind::OutOfRange {
+                key,
+                value,
+                expected: "a finite number that is not negative",
+            })
+        }
+    }
+
+    /// Ensures that every argument and parameter was used.
+    fn finish(mut self) -> Result<(), SceneErrorKind> {
+        if let Some(argument) = self.arguments.next() {
+            return Err(SceneErrorKind::UnexpectedArgument(argument.to_string()));
+        }
+
+        match self.parameters.into_keys().min() {
+            Some(key) => Err(SceneErrorKind::UnknownParameter(key.to_string())),
+            None => Ok(()),
+        }
+    }
+}
+
+struct SceneBuilder<'a> {
+    directory: PathBuf,
+    rng: &'a mut UsedRng,
+    camera: Option<CameraSettings>,
+    background: Option<Box<dyn Background>>,
+    textures: HashMap<String, Arc<dyn Texture>>,
+    materials: HashMap<String, Arc<dyn Material>>,
+    /// Meshes that have already been loaded with each material, which are shared between their
+    /// uses.
+    meshes: HashMap<(PathBuf, Option<String>), Arc<Mesh>>,
+    /// Density grids that have already been loaded.
+    grids: HashMap<PathBuf, Arc<DensityGrid>>,
+    objects: Vec<Box<dyn Hittable>>,
+    files: Vec<PathBuf>,
+}
+impl<'a> SceneBuilder<'a> {
+    fn new(directory: &Path, rng: &'a mut UsedRng) -> Self {
+        Self {
+            directory: directory.to_path_buf(),
+            rng,
+            camera: None,
+            background: None,
+            textures: HashMap::new(),
+            materials: HashMap::new(),
+            meshes: HashMap::new(),
+            grids: HashMap::new(),
+            objects: Vec::new(),
+            files: Vec::new(),
+        }
+    }
+
+    fn statement(&mut self, code: &str) -> Result<(), SceneErrorKind> {
+        let mut statement = Statement::new(code)?;
+
+        match statement.keyword {
+            "camera" => {
+                if self.camera.is_some() {
+                    return Err(SceneErrorKind::AlreadyDefined("camera"));
+                }
+
+                let settings = CameraSettings::default();
+                let settings =
+                    statement.update("look_from", settings, CameraSettings::look_from)?;
+                let settings = statement.update("look_at", settings, CameraSettings::look_at)?;
+                let settings = statement.update("up", settings, CameraSettings::up_direction)?;
+                let settings = statement.update("vfov", settings, CameraSettings::vertical_fov)?;
+                let settings =
+                    statement.update("defocus_angle", settings, CameraSettings::defocus_angle)?;
+                let settings =
+                    statement.update("focus_distance", settings, CameraSettings::focus_distance)?;
+                let settings =
+                    statement.update("shutter_open", settings, CameraSettings::shutter_open)?;
+                let settings =
+                    statement.update("shutter_close", settings, CameraSettings::shutter_close)?;
+                let settings = statement.update_count(
+                    "samples",
+                    settings,
+                    CameraSettings::samples_per_pixel,
+                )?;
+                let settings = statement.update(
+                    "min_samples",
+                    settings,
+                    CameraSettings::min_samples_per_pixel,
+                )?;
+                let settings = match statement.positive("noise_threshold")? {
+                    Some(threshold) => settings.noise_threshold(threshold),
+                    None => settings,
+                };
+                let settings =
+                    statement.update_count("max_depth", settings, CameraSettings::max_depth)?;
+                self.camera = Some(settings);
+            }
+            "background" => {
+                if self.background.is_some() {
+                    return Err(SceneErrorKind::AlreadyDefined("background"));
+                }
+
+                self.background = Some(match statement.argument("a background type")? {
+                    "solid" => Box::new(Solid::new(statement.required("color")?)),
+                    "gradient" => Box::new(Gradient::new(
+                        statement.or("bottom", Gradient::SKY_BOTTOM)?,
+                        statement.or("top", Gradient::SKY_TOP)?,
+                    )),
+                    "image" => {
+                        let path = self.directory.join(statement.required::<String>("file")?);
+                        Box::new(
+                            EnvironmentMap::new(self.image(path)?)
+                                .with_rotation(statement.or("rotation", 0.)?)
+                                .with_intensity(statement.or("intensity", 1.)?),
+                        )
+                    }
+                    "sky" => Box::new(
+                        Sky::new(
+                            statement.or("sun", Vector::new(1., 1., 1.))?,
+                            statement.or("turbidity", 3.)?,
+                        )
+                        .with_sun_diameter(
+                            statement
+                                .positive("sun_diameter")?
+                                .unwrap_or(Sky::SUN_DIAMETER),
+                        )
+                        .with_intensity(statement.or("intensity", 1.)?),
+                    ),
+                    name => {
+                        return Err(SceneErrorKind::UnknownType {
+                            what: "background",
+                            name: name.to_string(),
+                            expected: "solid, gradient, image, sky",
+                        })
+                    }
+                });
+            }
+            "texture" => {
+                let name = statement.argument("a texture name")?;
+                if self.textures.contains_key(name) {
+                    return Err(SceneErrorKind::DuplicateTexture(name.to_string()));
+                }
+
+                let mut noise = |statement: &mut Statement, style| -> Result<_, SceneErrorKind> {
+                    Ok(Arc::new(NoiseTexture::new(
+                        Perlin::new(self.rng),
+                        statement.or("scale", 1.)?,
+                        style,
+                    )))
+                };
+                let texture: Arc<dyn Texture> = match statement.argument("a texture type")? {
+                    "solid" => Arc::new(SolidColor::new(statement.required("color")?)),
+                    "checker" => Arc::new(Checker::new(
+                        statement.or("scale", 1.)?,
+                        self.texture(&mut statement, "even")?,
+                        self.texture(&mut statement, "odd")?,
+                    )),
+                    "image" => {
+                        let path = self.directory.join(statement.required::<String>("file")?);
+                        Arc::new(ImageTexture::new(self.image(path)?))
+                    }
+                    "noise" => noise(&mut statement, NoiseStyle::Smooth)?,
+                    "turbulence" => noise(&mut statement, NoiseStyle::Turbulence)?,
+                    "marble" => noise(&mut statement, NoiseStyle::Marble)?,
+                    name => {
+                        return Err(SceneErrorKind::UnknownType {
+                            what: "texture",
+                            name: name.to_string(),
+                            expected: "solid, checker, image, noise, turbulence, marble",
+                        })
+                    }
+                };
+                self.textures.insert(name.to_string(), texture);
+            }
+            "material" => {
+                let name = statement.argument("a material name")?;
+                if self.materials.contains_key(name) {
+                    return Err(SceneErrorKind::DuplicateMaterial(name.to_string()));
+                }
+
+                let material: Arc<dyn Material> = match statement.argument("a material type")? {
+                    "lambertian" => {
+                        Arc::new(Lambertian::new(self.texture(&mut statement, "albedo")?))
+                    }
+                    "metal" => Arc::new(Metal::new(
+                        self.texture(&mut statement, "albedo")?,
+                        statement.or("fuzz", 0.)?,
+                    )),
+                    "dielectric" => Arc::new(Dielectric::new(statement.required("ior")?)),
+                    "light" => Arc::new(DiffuseLight::new(self.texture(&mut statement, "emit")?)),
+                    "isotropic" => {
+                        Arc::new(Isotropic::new(self.texture(&mut statement, "albedo")?))
+                    }
+                    name => {
+                        return Err(SceneErrorKind::UnknownType {
+                            what: "material",
+                            name: name.to_string(),
+                            expected: "lambertian, metal, dielectric, light, isotropic",
+                        })
+                    }
+                };
+                self.materials.insert(name.to_string(), material);
+            }
+            "sphere" => {
+                let center = statement.required("center")?;
+                let radius = statement.required("radius")?;
+                let material = self.material(&mut statement)?;
+                match statement.optional("center_end")? {
+                    Some(end) => self.add_object(
+                        &mut statement,
+                        MovingSphere::new(center, end, radius, material.clone()),
+                        material,
+                    )?,
+                    None => self.add_object(
+                        &mut statement,
+                        Sphere::new(center, radius, material.clone()),
+                        material,
+                    )?,
+                }
+            }
+            "triangle" => {
+                let vertices = [
+                    statement.required("a")?,
+                    statement.required("b")?,
+                    statement.required("c")?,
+                ];
+                let material = self.material(&mut statement)?;
+                self.add_object(
+                    &mut statement,
+                    Triangle::new(vertices, material.clone()),
+                    material,
+                )?;
+            }
+            "quad" => {
+                let corner = statement.required("corner")?;
+                let u = statement.required("u")?;
+                let v = statement.required("v")?;
+                let material = self.material(&mut statement)?;
+                self.add_object(
+                    &mut statement,
+                    Quad::new(corner, u, v, material.clone()),
+                    material,
+                )?;
+            }
+            "disk" => {
+                let center = statement.required("center")?;
+                let normal = statement.required("normal")?;
+                let radius = statement.required("radius")?;
+                let material = self.material(&mut statement)?;
+                self.add_object(
+                    &mut statement,
+                    Disk::new(center, normal, radius, material.clone()),
+                    material,
+                )?;
+            }
+            "box" => {
+                let a = statement.required("a")?;
+                let b = statement.required("b")?;
+                let material = self.material(&mut statement)?;
+                self.add_object(
+                    &mut statement,
+                    Cuboid::new(a, b, material.clone()),
+                    material,
+                )?;
+            }
+            "mesh" => {
+                let path = self.directory.join(statement.required::<String>("file")?);
+                let material_name = statement.optional::<String>("material")?;
+                let material = match material_name.clone() {
+                    Some(name) => self.lookup_material(name)?,
+                    None => Arc::new(Lambertian::from_color(Color::new(0.5, 0.5, 0.5))),
+                };
+                let key = (path, material_name);
+                let mesh = match self.meshes.get(&key) {
+                    Some(mesh) => mesh.clone(),
+                    None => {
+                        let mesh = match Mesh::load_obj(&key.0, material.clone()) {
+                            Ok(mesh) => Arc::new(mesh),
+                            Err(error) => return Err(SceneErrorKind::Mesh { path: key.0, error }),
+                        };
+                        self.files.extend_from_slice(mesh.files());
+                        self.meshes.insert(key, mesh.clone());
+                        mesh
+                    }
+                };
+                self.add_object(&mut statement, mesh, material)?;
+            }
+            "volume" => {
+                let path = self.directory.join(statement.required::<String>("file")?);
+                let a = statement.required("a")?;
+                let b = statement.required("b")?;
+                let absorption = statement.non_negative_or("absorption", 0.)?;
+                let scattering = statement.non_negative_or("scattering", 1.)?;
+                // Rays pass through volumes, so they cannot bound another one
+                if statement.optional::<String>("density")?.is_some() {
+                    return Err(SceneErrorKind::VolumeDensity);
+                }
+                let emission = statement.or("emission", Color::zero())?;
+                let grid = match self.grids.get(&path) {
+                    Some(grid) => grid.clone(),
+                    None => {
+                        let grid = match DensityGrid::load(&path) {
+                            Ok(grid) => Arc::new(grid),
+                            Err(error) => return Err(SceneErrorKind::Volume { path, error }),
+                        };
+                        self.files.push(path.clone());
+                        self.grids.insert(path, grid.clone());
+                        grid
+                    }
+                };
+                let particles = Arc::new(Isotropic::new(Arc::new(SolidColor::new(Color::new(
+                    1., 1., 1.,
+                )))));
+                self.add_object(
+                    &mut statement,
+                    GridVolume::new(grid, a, b, absorption, scattering, emission),
+                    particles,
+                )?;
+            }
+            keyword => return Err(SceneErrorKind::UnknownStatement(keyword.to_string())),
+        }
+
+        statement.finish()
+    }
+
+    /// Gets the texture given by a parameter, which is either the name of a texture or a color.
+    fn texture(
+        &self,
+        statement: &mut Statement,
+        key: &'static str,
+    ) -> Result<Arc<dyn Texture>, SceneErrorKind> {
+        let value: String = statement.required(key)?;
+
+        // Anything that looks like a vector should be a color
+        if value.contains(',') {
+            return Ok(Arc::new(SolidColor::new(Vector::parse(key, &value)?)));
+        }
+
+        self.textures
+            .get(&value)
+            .cloned()
+            .ok_or(SceneErrorKind::UnknownTexture(value))
+    }
+
+    /// Adds an object made of the `material` to the scene, moved into place by any transform
+    /// parameters of the statement, and filled with a volume if a density is given.
+    ///
+    /// The object is scaled first, then rotated about the x, y and z axes in that order, and
+    /// finally translated.
+    fn add_object(
+        &mut self,
+        statement: &mut Statement,
+        object: impl Hittable + Send + 'static,
+        material: Arc<dyn Material>,
+    ) -> Result<(), SceneErrorKind> {
+        let scale = statement.optional::<Vector>("scale")?;
+        let rotate = statement.optional::<Vector>("rotate")?;
+        let translate = statement.optional::<Vector>("translate")?;
+        let object: Box<dyn Hittable> = if scale.is_none()
+            && rotate.is_none()
+            && translate.is_none()
+        {
+            Box::new(object)
+        } else {
+            let scale = scale.unwrap_or(Vector::new(1., 1., 1.));
+            let rotate = rotate.unwrap_or(Vector::zero());
+            let transform = Matrix::from_translation(translate.unwrap_or(Vector::zero()))
+                * Matrix::from_angle_z(Deg(rotate.z))
+                * Matrix::from_angle_y(Deg(rotate.y))
+                * Matrix::from_angle_x(Deg(rotate.x))
+                * Matrix::from_nonuniform_scale(scale.x, scale.y, scale.z);
+            Box::new(Transformed::new(object, transform).ok_or(SceneErrorKind::SingularTransform)?)
+        };
+
+        // The volume is outside of the transform so that its density is not scaled
+        match statement.positive("density")? {
+            Some(density) => self
+                .objects
+                .push(Box::new(ConstantMedium::new(object, density, material))),
+            None => self.objects.push(object),
+        }
+        Ok(())
+    }
+
+    /// Loads an image file.
+    fn image(&mut self, path: PathBuf) -> Result<Image, SceneErrorKind> {
+        match read_image(&path) {
+            Ok(image) => {
+                self.files.push(path);
+                Ok(image)
+            }
+            Err(error) => Err(SceneErrorKind::Image { path, error }),
+        }
+    }
+
+    /// Looks up the material named by the `material` parameter.
+    fn material(&self, statement: &mut Statement) -> Result<Arc<dyn Material>, SceneErrorKind> {
+        self.lookup_material(statement.required("material")?)
+    }
+
+    fn lookup_material(&self, name: String) -> Result<Arc<dyn Material>, SceneErrorKind> {
+        self.materials
+            .get(&name)
+            .cloned()
+            .ok_or(SceneErrorKind::UnknownMaterial(name))
+    }
+
+    fn build(self) -> Scene {
+        Scene {
+            camera: self.camera.unwrap_or_default(),
+            background: self
+                .background
+                .unwrap_or_else(|| Box::new(Gradient::default())),
+            objects: self.objects,
+            files: self.files,
+        }
+    }
+}
@@ -0,0 +1,182 @@
+//! Textures that vary the color of materials over surfaces.
+
+use crate::{
+    image::{Color, Image},
+    math::{Point, Vector, VectorExt},
+    UsedRng,
+};
+use cgmath::InnerSpace;
+use derive_new::new;
+use easy_cast::{Conv, ConvFloat};
+use rand::seq::SliceRandom;
+use std::sync::Arc;
+
+/// A color that varies over the surface of an object.
+pub trait Texture: std::fmt::Debug + Send + Sync {
+    /// The color at the surface coordinates `(u, v)`, which correspond to the point in space.
+    fn value(&self, u: f64, v: f64, point: &Point) -> Color;
+}
+
+/// The same color everywhere.
+#[derive(new, Debug)]
+pub struct SolidColor {
+    color: Color,
+}
+impl Texture for SolidColor {
+    fn value(&self, _u: f64, _v: f64, _point: &Point) -> Color {
+        self.color
+    }
+}
+
+/// A checkerboard pattern of cubes in space, alternating between two textures.
+#[derive(Debug)]
+pub struct Checker {
+    inverse_scale: f64,
+    even: Arc<dyn Texture>,
+    odd: Arc<dyn Texture>,
+}
+impl Checker {
+    /// The `scale` is the side length of each cube.
+    pub fn new(scale: f64, even: Arc<dyn Texture>, odd: Arc<dyn Texture>) -> Self {
+        Self {
+            inverse_scale: 1. / scale,
+            even,
+            odd,
+        }
+    }
+}
+impl Texture for Checker {
+    fn value(&self, u: f64, v: f64, point: &Point) -> Color {
+        let cell = |x: f64| (self.inverse_scale * x).floor();
+
+        if (cell(point.x) + cell(point.y) + cell(point.z)).rem_euclid(2.) == 0. {
+            self.even.value(u, v, point)
+        } else {
+            self.odd.value(u, v, point)
+        }
+    }
+}
+
+/// An image mapped onto the surface coordinates, with `(0, 0)` at the bottom left.
+#[derive(new, Debug)]
+pub struct ImageTexture {
+    image: Image,
+}
+impl Texture for ImageTexture {
+    fn value(&self, u: f64, v: f64, _point: &Point) -> Color {
+        let size = self.image.size();
+        let pixel =
+            |x: f64, len: usize| usize::conv_floor(x.clamp(0., 1.) * f64::conv(len)).min(len - 1);
+
+        // Images are stored from the top down
+        let x = pixel(u, size.width);
+        let y = pixel(1. - v, size.height);
+        self.image.pixel_data()[y * size.width + x]
+    }
+}
+
+/// Number of random gradients in the Perlin noise lattice.
+const PERLIN_POINTS: usize = 256;
+/// Number of noise octaves summed for turbulence.
+const TURBULENCE_DEPTH: usize = 7;
+
+/// Gradient noise on a lattice of random unit vectors, as introduced by Ken Perlin.
+#[derive(Debug)]
+pub struct Perlin {
+    gradients: Box<[Vector]>,
+    permutations: [Box<[usize]>; 3],
+}
+impl Perlin {
+    /// Generates the random lattice.
+    pub fn new(rng: &mut UsedRng) -> Self {
+        let mut permutation = || {
+            let mut p = (0..PERLIN_POINTS).collect::<Box<[_]>>();
+            p.shuffle(rng);
+            p
+        };
+        let permutations = [permutation(), permutation(), permutation()];
+
+        Self {
+            gradients: (0..PERLIN_POINTS)
+                .map(|_| Vector::random(rng, -1.0..1.).normalize())
+                .collect(),
+            permutations,
+        }
+    }
+
+    /// Smoothly varying noise in the range `[-1, 1]`.
+    pub fn noise(&self, point: &Point) -> f64 {
+        let floor = point.map(f64::floor);
+        let fraction = point - floor;
+        let lattice = floor.map(i64::conv_nearest);
+
+        // Hermite smoothing of the interpolation weights
+        let smooth = fraction.map(|x| x * x * (3. - 2. * x));
+
+        let mut total = 0.;
+        for (i, j, k) in itertools::iproduct!(0..2, 0..2, 0..2) {
+            let index = |axis: usize, offset: i64| {
+                self.permutations[axis][usize::conv((lattice[axis] + offset).rem_euclid(256))]
+            };
+            let gradient = self.gradients[index(0, i) ^ index(1, j) ^ index(2, k)];
+
+            let corner = Vector::new(f64::conv(i), f64::conv(j), f64::conv(k));
+            let weight = |axis: usize| {
+                corner[axis] * smooth[axis] + (1. - corner[axis]) * (1. - smooth[axis])
+            };
+
+            total += weight(0) * weight(1) * weight(2) * gradient.dot(fraction - corner);
+        }
+
+        total
+    }
+
+    /// The sum of octaves of noise with decreasing amplitude, which is always positive.
+    pub fn turbulence(&self, point: &Point) -> f64 {
+        let mut total = 0.;
+        let mut point = *point;
+        let mut weight = 1.;
+
+        for _ in 0..TURBULENCE_DEPTH {
+            total += weight * self.noise(&point);
+            weight *= 0.5;
+            point *= 2.;
+        }
+
+        total.abs()
+    }
+}
+
+/// The ways that Perlin noise can be turned into a texture.
+#[derive(Debug, Clone, Copy)]
+pub enum NoiseStyle {
+    /// Plain smooth noise.
+    Smooth,
+    /// Turbulence, giving a camouflage-like netting.
+    Turbulence,
+    /// Stripes distorted by turbulence, resembling marble.
+    Marble,
+}
+
+/// A gray texture generated from Perlin noise.
+#[derive(new, Debug)]
+pub struct NoiseTexture {
+    perlin: Perlin,
+    /// The noise frequency, with larger values giving finer detail.
+    scale: f64,
+    style: NoiseStyle,
+}
+impl Texture for NoiseTexture {
+    fn value(&self, _u: f64, _v: f64, point: &Point) -> Color {
+        let scaled = self.scale * point;
+        let intensity = match self.style {
+            NoiseStyle::Smooth => 0.5 * (1. + self.perlin.noise(&scaled)),
+            NoiseStyle::Turbulence => self.perlin.turbulence(&scaled),
+            NoiseStyle::Marble => {
+                0.5 * (1. + (scaled.z + 10. * self.perlin.turbulence(point)).sin())
+            }
+        };
+
+        Color::new(1., 1., 1.) * intensity
+    }
+}
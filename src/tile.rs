@@ -0,0 +1,184 @@
+//! Division of images into rectangular tiles that are rendered independently.
+
+use crate::image::Size;
+use easy_cast::Conv;
+use itertools::iproduct;
+
+/// The order in which the tiles of an image are rendered.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TileOrder {
+    /// Row by row, starting from the top left.
+    #[default]
+    Scanline,
+    /// Spiralling outwards from the center, where the subject of the image usually is.
+    Spiral,
+    /// Along a Hilbert curve, which keeps successive tiles close together.
+    Hilbert,
+}
+impl TileOrder {
+    /// Divides an image into square tiles with sides of `tile_size` pixels, in this order.
+    ///
+    /// The tiles along the right and bottom edges are smaller if the image size is not a
+    /// multiple of the tile size.
+    pub fn tiles(&self, image_size: Size<usize>, tile_size: usize) -> Vec<Tile> {
+        let grid = Self::grid(image_size, tile_size);
+        self.cells(grid)
+            .into_iter()
+            .map(|cell| Tile::from_cell(cell, image_size, tile_size))
+            .collect()
+    }
+
+    /// Divides the row major `pixels` of an image into tiles as with [`TileOrder::tiles`], along
+    /// with the rows of pixels of each tile, which can be written independently of the others.
+    pub fn split_pixels<'a, T>(
+        &self,
+        pixels: &'a mut [T],
+        image_size: Size<usize>,
+        tile_size: usize,
+    ) -> Vec<(Tile, Vec<&'a mut [T]>)> {
+        assert_eq!(
+            pixels.len(),
+            image_size.len(),
+            "the pixels must fill the image"
+        );
+        let grid = Self::grid(image_size, tile_size);
+        if grid.len() == 0 {
+            return Vec::new();
+        }
+
+        // Cut every row at the tile boundaries, gathering the pieces of each cell of the grid
+        let mut cell_rows = std::iter::repeat_with(Vec::new)
+            .take(grid.len())
+            .collect::<Vec<_>>();
+        for (y, row) in pixels.chunks_mut(image_size.width).enumerate() {
+            for (column, piece) in row.chunks_mut(tile_size).enumerate() {
+                cell_rows[(y / tile_size) * grid.width + column].push(piece);
+            }
+        }
+
+        self.cells(grid)
+            .into_iter()
+            .map(|(column, row)| {
+                (
+                    Tile::from_cell((column, row), image_size, tile_size),
+                    std::mem::take(&mut cell_rows[row * grid.width + column]),
+                )
+            })
+            .collect()
+    }
+
+    /// The number of tiles across and down the image.
+    fn grid(image_size: Size<usize>, tile_size: usize) -> Size<usize> {
+        Size::new(
+            image_size.width.div_ceil(tile_size),
+            image_size.height.div_ceil(tile_size),
+        )
+    }
+
+    /// The column and row of each cell of the grid, in this order.
+    fn cells(&self, grid: Size<usize>) -> Vec<(usize, usize)> {
+        match self {
+            Self::Scanline => iproduct!(0..grid.height, 0..grid.width)
+                .map(|(row, column)| (column, row))
+                .collect(),
+            Self::Spiral => Self::spiral(grid),
+            Self::Hilbert => {
+                let mut cells = iproduct!(0..grid.width, 0..grid.height).collect::<Vec<_>>();
+                let side = grid.width.max(grid.height).next_power_of_two();
+                cells.sort_by_key(|&(column, row)| Self::hilbert_index(side, column, row));
+                cells
+            }
+        }
+    }
+
+    /// Walks a square spiral outwards from the center cell, keeping the cells that are in the grid.
+    fn spiral(grid: Size<usize>) -> Vec<(usize, usize)> {
+        const DIRECTIONS: [(isize, isize); 4] = [(1, 0), (0, 1), (-1, 0), (0, -1)];
+
+        let total = grid.len();
+        let mut cells = Vec::with_capacity(total);
+        let inside = |(column, row): (isize, isize)| {
+            usize::try_from(column)
+                .ok()
+                .zip(usize::try_from(row).ok())
+                .filter(|&(column, row)| column < grid.width && row < grid.height)
+        };
+
+        let mut position = (
+            isize::conv((grid.width.max(1) - 1) / 2),
+            isize::conv((grid.height.max(1) - 1) / 2),
+        );
+        cells.extend(inside(position));
+
+        // Each side length is used for two sides of the spiral before increasing
+        let mut side = 1;
+        let mut direction = DIRECTIONS.iter().cycle();
+        while cells.len() < total {
+            for _ in 0..2 {
+                let (dx, dy) = direction.next().unwrap();
+                for _ in 0..side {
+                    position = (position.0 + dx, position.1 + dy);
+                    cells.extend(inside(position));
+                }
+            }
+            side += 1;
+        }
+
+        cells
+    }
+
+    /// The distance along a Hilbert curve filling a square with the `side` length, which must be a
+    /// power of two.
+    fn hilbert_index(side: usize, mut x: usize, mut y: usize) -> usize {
+        let mut index = 0;
+        let mut s = side / 2;
+        while s > 0 {
+            let rx = usize::from(x & s > 0);
+            let ry = usize::from(y & s > 0);
+            index += s * s * ((3 * rx) ^ ry);
+
+            // Rotate the quadrant so that the curve is continuous
+            if ry == 0 {
+                if rx == 1 {
+                    x = side - 1 - x;
+                    y = side - 1 - y;
+                }
+                std::mem::swap(&mut x, &mut y);
+            }
+            s /= 2;
+        }
+
+        index
+    }
+}
+
+/// A rectangular region of an image, with the position of its top left pixel.
+#[derive(Debug, Clone, Copy)]
+pub struct Tile {
+    pub x: usize,
+    pub y: usize,
+    pub size: Size<usize>,
+}
+impl Tile {
+    /// The tile in the `column` and `row` of the grid of tiles of an image.
+    fn from_cell((column, row): (usize, usize), image_size: Size<usize>, tile_size: usize) -> Self {
+        let (x, y) = (column * tile_size, row * tile_size);
+        Self {
+            x,
+            y,
+            size: Size::new(
+                tile_size.min(image_size.width - x),
+                tile_size.min(image_size.height - y),
+            ),
+        }
+    }
+
+    /// The image coordinates of each pixel in the tile, in row major order.
+    pub fn pixels(&self) -> impl Iterator<Item = (usize, usize)> {
+        iproduct!(
+            self.y..self.y + self.size.height,
+            self.x..self.x + self.size.width
+        )
+        .map(|(y, x)| (x, y))
+    }
+}
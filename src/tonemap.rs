@@ -0,0 +1,110 @@
+//! Conversion of linear radiance colors to display colors.
+//!
+//! The rendered colors can be arbitrarily bright, so are first scaled by the exposure, then
+//! compressed into the displayable range by a tone mapping operator, and finally encoded with
+//! the sRGB transfer function.
+
+use crate::image::Color;
+use cgmath::InnerSpace;
+
+/// Coefficients of the linear RGB channels for computing luminance, according to Rec. 709.
+pub(crate) const LUMINANCE_WEIGHTS: Color = Color::new(0.2126, 0.7152, 0.0722);
+
+/// The tone mapping curve used to bring linear colors into the displayable range.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ToneMapOperator {
+    /// Simply saturates channels above one.
+    #[default]
+    Clamp,
+    /// The simple Reinhard operator applied to luminance, which never quite reaches white.
+    Reinhard,
+    /// The Reinhard operator applied to luminance, extended so that the white point maps to white.
+    ExtendedReinhard,
+    /// Krzysztof Narkowicz's fit of the ACES filmic curve.
+    Aces,
+    /// John Hable's filmic curve from Uncharted 2.
+    Hable,
+}
+impl ToneMapOperator {
+    /// Maps a linear color into a linear color in the range `[0, 1]`, except possibly for some
+    /// channels that are saturated by luminance based operators and later clamped.
+    fn map(&self, color: Color, white_point: f64) -> Color {
+        match self {
+            Self::Clamp => color,
+            Self::Reinhard => Self::scale_luminance(color, |l| l / (1. + l)),
+            Self::ExtendedReinhard => {
+                Self::scale_luminance(color, |l| l * (1. + l / white_point.powi(2)) / (1. + l))
+            }
+            Self::Aces => color.map(|x| {
+                // The fit expects the input to be scaled down to match the ACES reference exposure
+                let x = 0.6 * x;
+                (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14)
+            }),
+            Self::Hable => {
+                // The exposure bias and linear white point suggested by Hable
+                const EXPOSURE_BIAS: f64 = 2.;
+                const WHITE: f64 = 11.2;
+
+                color.map(|x| Self::hable_partial(EXPOSURE_BIAS * x) / Self::hable_partial(WHITE))
+            }
+        }
+    }
+
+    /// Scales the color so that its luminance is mapped by the function.
+    fn scale_luminance(color: Color, f: impl Fn(f64) -> f64) -> Color {
+        let luminance = color.dot(LUMINANCE_WEIGHTS);
+        if luminance > 0. {
+            color * (f(luminance) / luminance)
+        } else {
+            color
+        }
+    }
+
+    fn hable_partial(x: f64) -> f64 {
+        const A: f64 = 0.15;
+        const B: f64 = 0.5;
+        const C: f64 = 0.1;
+        const D: f64 = 0.2;
+        const E: f64 = 0.02;
+        const F: f64 = 0.3;
+
+        (x * (A * x + C * B) + D * E) / (x * (A * x + B) + D * F) - E / F
+    }
+}
+
+/// How linear image colors are converted to display colors.
+#[derive(Debug, Clone)]
+pub struct ToneMapping {
+    /// Exposure adjustment in stops, so that each increment doubles the brightness.
+    pub exposure: f64,
+    /// The curve used to compress the range of colors.
+    pub operator: ToneMapOperator,
+    /// The luminance that is mapped to white, which only affects [`ToneMapOperator::ExtendedReinhard`].
+    pub white_point: f64,
+}
+impl ToneMapping {
+    /// Converts a linear color to an sRGB encoded display color with channels in `[0, 1]`.
+    pub fn map(&self, color: Color) -> Color {
+        self.operator
+            .map(color * self.exposure.exp2(), self.white_point)
+            .map(|x| Self::srgb_transfer(x.clamp(0., 1.)))
+    }
+
+    /// The sRGB transfer function, which is the gamma correction from linear to display values.
+    fn srgb_transfer(x: f64) -> f64 {
+        if x <= 0.0031308 {
+            12.92 * x
+        } else {
+            1.055 * x.powf(1. / 2.4) - 0.055
+        }
+    }
+}
+impl Default for ToneMapping {
+    fn default() -> Self {
+        Self {
+            exposure: 0.,
+            operator: ToneMapOperator::default(),
+            white_point: 4.,
+        }
+    }
+}
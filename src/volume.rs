@@ -0,0 +1,251 @@
+//! Volumes whose density varies in space, such as smoke and clouds, and loading them from grid
+//! files.
+//!
+//! A grid file is a plain text file starting with the number of voxels along the x, y and z axes,
+//! followed by the density of each voxel, all separated by whitespace.
+//! The densities are ordered with x changing fastest and then y, so that the voxel `(x, y, z)` is
+//! number `x + width * (y + height * z)`.
+//! As in scene files, `#` starts a comment that runs to the end of the line.
+
+use crate::{
+    hittable::{HitRecord, Hittable},
+    image::Color,
+    material::{DiffuseLight, Isotropic, Material},
+    math::{Aabb, Point, Ray, Vector},
+    texture::SolidColor,
+    UsedRng,
+};
+use cgmath::InnerSpace;
+use easy_cast::{Conv, ConvFloat};
+use itertools::iproduct;
+use rand::Rng;
+use std::{ops::RangeInclusive, path::Path, sync::Arc};
+use thiserror::Error;
+
+/// The reason a density grid could not be loaded.
+#[derive(Debug, Error)]
+pub enum VolumeError {
+    #[error("could not read grid file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("invalid grid size, expected three positive whole numbers that are not too large")]
+    InvalidSize,
+    #[error("invalid number `{0}`")]
+    InvalidNumber(String),
+    #[error("invalid density {0}, densities must be finite and not negative")]
+    InvalidDensity(f64),
+    #[error("the grid has {expected} voxels but {found} densities are given")]
+    WrongCount { expected: usize, found: usize },
+}
+
+/// A dense three dimensional grid of densities, one for each voxel.
+#[derive(Debug)]
+pub struct DensityGrid {
+    size: [usize; 3],
+    densities: Box<[f64]>,
+    /// The greatest density, which bounds the density everywhere within the grid.
+    max: f64,
+}
+impl DensityGrid {
+    /// A grid with the `size` number of voxels along each axis, with the `densities` ordered as in
+    /// grid files.
+    pub fn new(size: [usize; 3], densities: Vec<f64>) -> Result<Self, VolumeError> {
+        if size.contains(&0) {
+            return Err(VolumeError::InvalidSize);
+        }
+        let expected = size
+            .iter()
+            .try_fold(1, |count: usize, n| count.checked_mul(*n))
+            .ok_or(VolumeError::InvalidSize)?;
+        if densities.len() != expected {
+            return Err(VolumeError::WrongCount {
+                expected,
+                found: densities.len(),
+            });
+        }
+        if let Some(density) = densities.iter().find(|d| !d.is_finite() || **d < 0.) {
+            return Err(VolumeError::InvalidDensity(*density));
+        }
+
+        Ok(Self {
+            size,
+            max: densities.iter().copied().fold(0., f64::max),
+            densities: densities.into(),
+        })
+    }
+
+    /// Loads a grid file.
+    pub fn load(path: &Path) -> Result<Self, VolumeError> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    /// Reads the contents of a grid file.
+    pub fn parse(text: &str) -> Result<Self, VolumeError> {
+        let mut words = text
+            .lines()
+            .map(|line| line.split('#').next().unwrap_or_default())
+            .flat_map(str::split_whitespace);
+
+        let mut size = [0; 3];
+        for n in size.iter_mut() {
+            *n = words
+                .next()
+                .and_then(|word| word.parse().ok())
+                .ok_or(VolumeError::InvalidSize)?;
+        }
+        let densities = words
+            .map(|word| {
+                word.parse()
+                    .map_err(|_| VolumeError::InvalidNumber(word.to_string()))
+            })
+            .collect::<Result<_, _>>()?;
+
+        Self::new(size, densities)
+    }
+
+    /// The density at the coordinates, each in the range `[0, 1]` across the grid, interpolated
+    /// trilinearly between the centers of the voxels.
+    pub fn density(&self, coordinates: [f64; 3]) -> f64 {
+        let mut lower = [0; 3];
+        let mut upper = [0; 3];
+        let mut fraction = [0.; 3];
+        for axis in 0..3 {
+            // Beyond the outer voxel centers the density is that of the nearest voxel
+            let last = self.size[axis] - 1;
+            let x =
+                (coordinates[axis] * f64::conv(self.size[axis]) - 0.5).clamp(0., f64::conv(last));
+            lower[axis] = usize::conv_floor(x).min(last);
+            upper[axis] = (lower[axis] + 1).min(last);
+            fraction[axis] = x - f64::conv(lower[axis]);
+        }
+
+        iproduct!(0..2, 0..2, 0..2)
+            .map(|(dx, dy, dz)| {
+                let mut index = [0; 3];
+                let mut weight = 1.;
+                for (axis, d) in [dx, dy, dz].into_iter().enumerate() {
+                    if d == 0 {
+                        index[axis] = lower[axis];
+                        weight *= 1. - fraction[axis];
+                    } else {
+                        index[axis] = upper[axis];
+                        weight *= fraction[axis];
+                    }
+                }
+                weight * self.voxel(index)
+            })
+            .sum()
+    }
+
+    fn voxel(&self, [x, y, z]: [usize; 3]) -> f64 {
+        self.densities[x + self.size[0] * (y + self.size[1] * z)]
+    }
+}
+
+/// A volume filling a box with a density grid, which absorbs, scatters and emits light in
+/// proportion to the density.
+///
+/// Rays are followed through it by delta tracking, in which collisions are proposed as if the
+/// whole volume had the greatest density, and each is then randomly kept as an absorption or
+/// scattering with the actual density, or otherwise ignored.
+pub struct GridVolume {
+    grid: Arc<DensityGrid>,
+    bounds: Aabb,
+    absorption: f64,
+    scattering: f64,
+    /// The material of absorption events, which ends paths with the emitted light.
+    absorber: DiffuseLight,
+    /// The material of scattering events.
+    particles: Isotropic,
+}
+impl GridVolume {
+    /// The `grid` stretched over the box with opposite corners `a` and `b`.
+    ///
+    /// The `absorption` and `scattering` coefficients are the chances of light being absorbed or
+    /// scattered per unit distance at unit density, and absorbed light is replaced with the
+    /// `emission`, which makes the volume glow like fire.
+    pub fn new(
+        grid: Arc<DensityGrid>,
+        a: Point,
+        b: Point,
+        absorption: f64,
+        scattering: f64,
+        emission: Color,
+    ) -> Self {
+        Self {
+            grid,
+            bounds: Aabb::from_corners(a, b).padded(),
+            absorption,
+            scattering,
+            absorber: DiffuseLight::new(Arc::new(SolidColor::new(emission))),
+            particles: Isotropic::new(Arc::new(SolidColor::new(Color::new(1., 1., 1.)))),
+        }
+    }
+
+    /// The density at a point within the box.
+    fn density(&self, point: Point) -> f64 {
+        let extent = self.bounds.max - self.bounds.min;
+        let offset = point - self.bounds.min;
+        self.grid.density([
+            offset.x / extent.x,
+            offset.y / extent.y,
+            offset.z / extent.z,
+        ])
+    }
+}
+impl Hittable for GridVolume {
+    /// Rays pass through the volume, with where they are absorbed or scattered being sampled
+    /// separately.
+    fn hit(&self, _ray: &Ray, _t_range: &RangeInclusive<f64>) -> Option<HitRecord<'_>> {
+        None
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bounds
+    }
+
+    fn sample_medium(
+        &self,
+        rng: &mut UsedRng,
+        ray: &Ray,
+        t_range: &RangeInclusive<f64>,
+    ) -> Option<HitRecord<'_>> {
+        let range = self.bounds.overlap(ray, t_range)?;
+        let majorant = self.grid.max * (self.absorption + self.scattering);
+        if majorant <= 0. {
+            return None;
+        }
+
+        let ray_length = ray.direction.magnitude();
+        let mut t = *range.start();
+        loop {
+            t -= (1. - rng.gen::<f64>()).ln() / (majorant * ray_length);
+            if t > *range.end() {
+                return None;
+            }
+
+            let density = self.density(ray.at(t));
+            let event = rng.gen::<f64>() * majorant;
+            let material: &dyn Material = if event < density * self.absorption {
+                &self.absorber
+            } else if event < density * (self.absorption + self.scattering) {
+                &self.particles
+            } else {
+                continue;
+            };
+
+            // The surface normal and coordinates are meaningless inside a volume
+            return Some(HitRecord::new(
+                self,
+                material,
+                ray,
+                t,
+                Vector::unit_x(),
+                (0., 0.),
+            ));
+        }
+    }
+
+    fn collect_media<'a>(&'a self, media: &mut Vec<Box<dyn Hittable + 'a>>) {
+        media.push(Box::new(self));
+    }
+}
@@ -0,0 +1,282 @@
+//! Writing of rendered images to files in various formats.
+//!
+//! The integer formats store tone mapped sRGB colors, whereas the
+//! high dynamic range formats store the raw linear colors as floating point.
+
+use crate::{
+    image::{Color, Image},
+    tonemap::ToneMapping,
+};
+use easy_cast::{Conv, ConvFloat};
+use std::{
+    fs::File,
+    io::{BufWriter, Seek, Write},
+    path::Path,
+};
+use thiserror::Error;
+
+/// The reason an image file could not be written.
+#[derive(Debug, Error)]
+pub enum WriteError {
+    #[error("unsupported image file extension `{0}`, expected one of: png, ppm, hdr, pfm, exr")]
+    UnsupportedExtension(String),
+    #[error("the image file name has no extension to determine the format from")]
+    MissingExtension,
+    #[error("could not write image file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("could not encode PNG image: {0}")]
+    Png(#[from] png::EncodingError),
+    #[error("could not encode OpenEXR image: {0}")]
+    Exr(#[from] exr::error::Error),
+}
+
+/// Number of bits used to store each color channel in integer image formats.
+///
+/// This has no effect on the high dynamic range formats, which always use floating point.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default)]
+pub enum BitDepth {
+    #[default]
+    #[value(name = "8")]
+    Eight,
+    #[value(name = "16")]
+    Sixteen,
+}
+impl BitDepth {
+    /// The maximum channel value.
+    fn max(&self) -> u16 {
+        match self {
+            Self::Eight => u8::MAX.into(),
+            Self::Sixteen => u16::MAX,
+        }
+    }
+}
+
+/// How the linear image colors are encoded in integer image formats.
+#[derive(Debug, Clone, Default)]
+pub struct EncodeSettings {
+    pub bit_depth: BitDepth,
+    pub tone_mapping: ToneMapping,
+}
+
+/// The supported image file formats.
+#[derive(Debug, Clone, Copy)]
+pub enum ImageFormat {
+    /// Portable Network Graphics.
+    Png,
+    /// Binary (P6) Portable PixMap.
+    Ppm,
+    /// Radiance RGBE, with run length encoding.
+    Hdr,
+    /// Portable Float Map, with 32-bit floating point channels.
+    Pfm,
+    /// OpenEXR, with 32-bit floating point channels.
+    Exr,
+}
+impl ImageFormat {
+    /// Determines the format from the extension of the file name.
+    pub fn from_path(path: &Path) -> Result<Self, WriteError> {
+        let extension = path
+            .extension()
+            .ok_or(WriteError::MissingExtension)?
+            .to_string_lossy();
+
+        match extension.to_lowercase().as_str() {
+            "png" => Ok(Self::Png),
+            "ppm" => Ok(Self::Ppm),
+            "hdr" => Ok(Self::Hdr),
+            "pfm" => Ok(Self::Pfm),
+            "exr" => Ok(Self::Exr),
+            _ => Err(WriteError::UnsupportedExtension(extension.into_owned())),
+        }
+    }
+
+    /// Encodes the image in this format.
+    pub fn write<W: Write + Seek>(
+        &self,
+        writer: W,
+        image: &Image,
+        settings: &EncodeSettings,
+    ) -> Result<(), WriteError> {
+        match self {
+            Self::Png => write_png(writer, image, settings),
+            Self::Ppm => write_ppm(writer, image, settings),
+            Self::Hdr => write_hdr(writer, image),
+            Self::Pfm => write_pfm(writer, image),
+            Self::Exr => write_exr(writer, image),
+        }
+    }
+}
+
+/// Writes the image to a file in the format, which is usually determined by [`ImageFormat::from_path`].
+pub fn write_image(
+    path: &Path,
+    format: ImageFormat,
+    image: &Image,
+    settings: &EncodeSettings,
+) -> Result<(), WriteError> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    format.write(&mut writer, image, settings)?;
+    writer.flush()?;
+
+    Ok(())
+}
+
+fn write_png<W: Write>(
+    writer: W,
+    image: &Image,
+    settings: &EncodeSettings,
+) -> Result<(), WriteError> {
+    let size = image.size();
+    let mut encoder = png::Encoder::new(writer, u32::conv(size.width), u32::conv(size.height));
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_source_srgb(png::SrgbRenderingIntent::Perceptual);
+    encoder.set_depth(match settings.bit_depth {
+        BitDepth::Eight => png::BitDepth::Eight,
+        BitDepth::Sixteen => png::BitDepth::Sixteen,
+    });
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(
+        &image.quantized_bytes(&settings.tone_mapping, settings.bit_depth.max()),
+    )?;
+    writer.finish()?;
+
+    Ok(())
+}
+
+fn write_ppm<W: Write>(
+    mut writer: W,
+    image: &Image,
+    settings: &EncodeSettings,
+) -> Result<(), WriteError> {
+    let size = image.size();
+    let max = settings.bit_depth.max();
+
+    // Header: P6 format, image size, and max color value
+    write!(writer, "P6\n{} {}\n{max}\n", size.width, size.height)?;
+    writer.write_all(&image.quantized_bytes(&settings.tone_mapping, max))?;
+
+    Ok(())
+}
+
+/// Converts a linear color to the shared exponent RGBE representation.
+fn rgbe(color: &Color) -> [u8; 4] {
+    let max = color.x.max(color.y).max(color.z);
+    if max < 1e-32 {
+        return [0; 4];
+    }
+
+    // Find the exponent such that the mantissa of the largest channel is in [0.5, 1)
+    let mut exponent = max.log2().floor() + 1.;
+    if max / exponent.exp2() >= 1. {
+        exponent += 1.;
+    }
+    let scale = 256. / exponent.exp2();
+    let mantissa = |x: f64| u8::conv_floor((x * scale).clamp(0., 255.));
+
+    [
+        mantissa(color.x),
+        mantissa(color.y),
+        mantissa(color.z),
+        u8::conv_nearest((exponent + 128.).clamp(0., 255.)),
+    ]
+}
+
+/// Writes one component of an RGBE scanline using the run length encoding of the new Radiance format.
+fn write_hdr_component<W: Write>(writer: &mut W, values: &[u8]) -> std::io::Result<()> {
+    /// Runs shorter than this are just written as part of literal spans.
+    const MIN_RUN: usize = 4;
+    /// Both runs and literal spans have a maximum length due to how their counts are stored.
+    const MAX_SPAN: usize = 127;
+
+    let run_length = |start: usize| {
+        values[start..]
+            .iter()
+            .take(MAX_SPAN)
+            .take_while(|v| **v == values[start])
+            .count()
+    };
+
+    let mut i = 0;
+    while i < values.len() {
+        let run = run_length(i);
+        if run >= MIN_RUN {
+            writer.write_all(&[u8::conv(128 + run), values[i]])?;
+            i += run;
+        } else {
+            // Gather values until the next worthwhile run
+            let mut end = i;
+            while end < values.len() && end - i < MAX_SPAN && run_length(end) < MIN_RUN {
+                end += 1;
+            }
+            writer.write_all(&[u8::conv(end - i)])?;
+            writer.write_all(&values[i..end])?;
+            i = end;
+        }
+    }
+
+    Ok(())
+}
+
+fn write_hdr<W: Write>(mut writer: W, image: &Image) -> Result<(), WriteError> {
+    let size = image.size();
+    write!(
+        writer,
+        "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n",
+        size.height, size.width
+    )?;
+
+    for row in image.pixel_data().chunks(size.width) {
+        let pixels = row.iter().map(rgbe).collect::<Vec<_>>();
+
+        // Run length encoding is only possible for certain widths
+        if (8..0x8000).contains(&size.width) {
+            writer.write_all(&[2, 2])?;
+            writer.write_all(&u16::conv(size.width).to_be_bytes())?;
+            for component in 0..4 {
+                write_hdr_component(
+                    &mut writer,
+                    &pixels.iter().map(|p| p[component]).collect::<Vec<_>>(),
+                )?;
+            }
+        } else {
+            writer.write_all(&pixels.concat())?;
+        }
+    }
+
+    Ok(())
+}
+
+fn write_pfm<W: Write>(mut writer: W, image: &Image) -> Result<(), WriteError> {
+    let size = image.size();
+
+    // Header: color format, image size, and scale with negative meaning little-endian
+    write!(writer, "PF\n{} {}\n-1.0\n", size.width, size.height)?;
+
+    // Rows are stored from the bottom up
+    for row in image.pixel_data().chunks(size.width).rev() {
+        for color in row {
+            for x in [color.x, color.y, color.z] {
+                writer.write_all(&(x as f32).to_le_bytes())?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn write_exr<W: Write + Seek>(writer: W, image: &Image) -> Result<(), WriteError> {
+    use exr::prelude::{SpecificChannels, Vec2, WritableImage};
+
+    let size = image.size();
+    let channels = SpecificChannels::rgb(|Vec2(x, y): Vec2<usize>| {
+        let color = image.pixel_data()[y * size.width + x];
+        (color.x as f32, color.y as f32, color.z as f32)
+    });
+
+    exr::prelude::Image::from_channels((size.width, size.height), channels)
+        .write()
+        .to_buffered(writer)?;
+
+    Ok(())
+}